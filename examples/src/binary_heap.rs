@@ -1,6 +1,6 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::must_use_candidate)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
 
 use std::collections::BinaryHeap;
@@ -91,21 +91,21 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut model = ModelBinaryHeap::<u16>::default();
     let mut tested = BinaryHeap::<u16>::with_capacity(capacity as usize);
 
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start(
+        "binary_heap",
+        format!("let mut v = BinaryHeap::<u16>::with_capacity({capacity});"),
+    );
+
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u16> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u16> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&op);
         op.execute_and_compare(&mut model, &mut tested);
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
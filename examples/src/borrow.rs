@@ -1,9 +1,9 @@
-use honggfuzz::fuzz;
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 use rutenspitz::arbitrary_stateful_operations;
 
 pub struct Extender<'a, T>(&'a mut Vec<T>);
 
-impl<'a, T: Clone> Extender<'a, T> {
+impl<T: Clone> Extender<'_, T> {
     fn extend_from_slice(&mut self, slice: &[T]) {
         self.0.extend_from_slice(slice);
     }
@@ -22,7 +22,6 @@ arbitrary_stateful_operations! {
     }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     use arbitrary::{Arbitrary, Unstructured};
 
@@ -32,7 +31,8 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut tested = Extender(&mut vec);
 
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u32> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u32> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
         op.execute(&mut tested);
     }
@@ -40,12 +40,4 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
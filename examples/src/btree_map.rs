@@ -1,15 +1,18 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::manual_filter_map)]
 #![allow(clippy::manual_find_map)]
+#![allow(clippy::missing_panics_doc)]
 #![allow(clippy::must_use_candidate)]
 #![allow(clippy::option_if_let_else)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::fork::Pairs;
 
 use std::collections::BTreeMap;
 use std::fmt::Debug;
 
-#[derive(Default)]
+// Kept sorted by key.
+#[derive(Clone, Default)]
 pub struct ModelBTreeMap<K, V>
 where
     K: Eq + Ord,
@@ -55,7 +58,8 @@ where
         if let Some(e) = self.data.iter_mut().find(|probe| probe.0 == k) {
             Some(std::mem::replace(&mut e.1, v))
         } else {
-            self.data.push((k, v));
+            let pos = self.data.partition_point(|probe| probe.0 < k);
+            self.data.insert(pos, (k, v));
             None
         }
     }
@@ -70,53 +74,60 @@ where
 
     pub fn remove(&mut self, k: &K) -> Option<V> {
         let pos = self.data.iter().position(|probe| probe.0 == *k);
-        pos.map(|idx| self.data.swap_remove(idx).1)
+        pos.map(|idx| self.data.remove(idx).1)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&K, &V)> + ExactSizeIterator {
         self.data.iter().map(|e| (&e.0, &e.1))
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl DoubleEndedIterator<Item = (&K, &mut V)> + ExactSizeIterator {
         self.data.iter_mut().map(|e| (&e.0, &mut e.1))
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &K> + ExactSizeIterator {
         self.data.iter().map(|e| &e.0)
     }
 
-    pub fn range(&mut self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &V)> {
+    pub fn range(&mut self, range: std::ops::Range<K>) -> impl DoubleEndedIterator<Item = (&K, &V)> {
         self.range_mut(range).map(|e| (e.0, &*e.1))
     }
 
-    pub fn range_mut(&mut self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &mut V)> {
+    pub fn range_mut(
+        &mut self,
+        range: std::ops::Range<K>,
+    ) -> impl DoubleEndedIterator<Item = (&K, &mut V)> {
+        assert!(range.start <= range.end, "range start is greater than range end");
         self.data
             .iter_mut()
             .filter(move |e| e.0 >= range.start && e.0 < range.end)
             .map(|e| (&e.0, &mut e.1))
     }
 
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.data.retain_mut(|e| f(&e.0, &mut e.1));
+    }
+
     pub fn split_off(&mut self, key: &K) -> impl IntoIterator<Item = (K, V)> {
         let (a, b) = self.data.drain(..).partition(|probe| probe.0 < *key);
         self.data = a;
         b
     }
 
-    pub fn values(&self) -> impl Iterator<Item = &V> {
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &V> + ExactSizeIterator {
         self.data.iter().map(|e| &e.1)
     }
 
-    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+    pub fn values_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut V> + ExactSizeIterator {
         self.data.iter_mut().map(|e| &mut e.1)
     }
 }
 
-fn sort_iterator<T: Ord, I: Iterator<Item = T>>(i: I) -> Vec<T> {
-    let mut v: Vec<_> = i.collect::<Vec<_>>();
-    v.sort();
-    v
-}
-
 fn sort_iterable<T: Ord, I: IntoIterator<Item = T>>(i: I) -> Vec<T> {
     let mut v: Vec<_> = i.into_iter().collect::<Vec<_>>();
     v.sort();
@@ -126,64 +137,75 @@ fn sort_iterable<T: Ord, I: IntoIterator<Item = T>>(i: I) -> Vec<T> {
 arbitrary_stateful_operations! {
     model = ModelBTreeMap<K, V>,
     tested = BTreeMap<K, V>,
+    fork = true,
 
     type_parameters = <
-        K: Clone + Copy + Debug + Eq + Ord,
+        K: Clone + Copy + Debug + Eq + Ord + rutenspitz::closures::Project,
         V: Clone + Copy + Debug + Eq + Ord
     >,
 
     methods {
         equal {
             fn clear(&mut self);
+            #[complexity(log n)]
             fn contains_key(&self, k: &K) -> bool;
+            #[complexity(log n)]
             fn get(&self, k: &K) -> Option<&V>;
+            #[complexity(log n)]
             fn get_key_value(&self, k: &K) -> Option<(&K, &V)>;
+            #[complexity(log n)]
             fn get_mut(&mut self, k: &K) -> Option<&mut V>;
+            #[complexity(log n)]
             fn insert(&mut self, k: K, v: V) -> Option<V>;
+            #[complexity(1)]
             fn is_empty(&self) -> bool;
+            #[complexity(1)]
             fn len(&self) -> usize;
+            #[complexity(log n)]
             fn remove(&mut self, k: &K) -> Option<V>;
+            fn retain(&mut self, f: impl FnMut(&K, &mut V) -> bool);
         }
 
-        equal_with(sort_iterator) {
+        iterate(next, next_back, nth, size_hint, len) {
             fn iter(&self) -> impl Iterator<Item = (&K, &V)>;
             fn iter_mut(&self) -> impl Iterator<Item = (&K, &mut V)>;
             fn keys(&self) -> impl Iterator<Item = &K>;
-            fn range(&self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &V)>;
-            fn range_mut(&self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &mut V)>;
             fn values(&self) -> impl Iterator<Item = &V>;
             fn values_mut(&mut self) -> impl Iterator<Item = &mut V>;
         }
 
+        iterate(next, next_back, nth) {
+            fn range(&self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &V)>;
+            fn range_mut(&self, range: std::ops::Range<K>) -> impl Iterator<Item = (&K, &mut V)>;
+        }
+
         equal_with(sort_iterable) {
             fn split_off(&mut self, k: &K) -> impl IntoIterator<Item = (&K, &V)>;
         }
     }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     use arbitrary::{Arbitrary, Unstructured};
 
     let mut ring = Unstructured::new(data);
-    let mut model = ModelBTreeMap::<u16, u16>::new();
-    let mut tested = BTreeMap::<u16, u16>::new();
+    let model = ModelBTreeMap::<u16, u16>::new();
+    let tested = BTreeMap::<u16, u16>::new();
+    let mut pairs = Pairs::new(model, tested);
+
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start("btree_map", "let mut v = BTreeMap::<u16, u16>::new();");
 
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring) {
-        op.append_to_trace(&mut op_trace);
-        op.execute_and_compare(&mut model, &mut tested);
+    while !ring.is_empty() {
+        let step = op::Step::<u16, u16>::arbitrary(&mut ring)?;
+        let step = pairs.resolve(step);
+        step.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&step);
+        pairs.execute_and_compare(step);
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
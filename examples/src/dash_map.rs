@@ -0,0 +1,103 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+#![allow(clippy::must_use_candidate)]
+
+use rutenspitz::arbitrary_stateful_operations;
+
+use dashmap::DashMap;
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModelDashMap<K, V>
+where
+    K: Eq + Hash,
+{
+    data: Vec<(K, V)>,
+}
+
+impl<K, V> ModelDashMap<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.data.iter().any(|probe| probe.0 == *k)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(entry) = self.data.iter_mut().find(|probe| probe.0 == k) {
+            Some(std::mem::replace(&mut entry.1, v))
+        } else {
+            self.data.push((k, v));
+            None
+        }
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<(K, V)> {
+        let pos = self.data.iter().position(|probe| probe.0 == *k);
+        pos.map(|idx| self.data.swap_remove(idx))
+    }
+
+    pub fn remove_if<F>(&mut self, k: &K, f: F) -> Option<(K, V)>
+    where
+        F: FnOnce(&K, &V) -> bool,
+    {
+        let pos = self.data.iter().position(|probe| probe.0 == *k)?;
+        let entry = &self.data[pos];
+        if f(&entry.0, &entry.1) {
+            Some(self.data.swap_remove(pos))
+        } else {
+            None
+        }
+    }
+}
+
+// `len` and `is_empty` are left out on purpose: they visit the shards one by
+// one and so do not take effect at a single point in time.
+arbitrary_stateful_operations! {
+    model = ModelDashMap<K, V>,
+    tested = DashMap<K, V>,
+    concurrent = true,
+
+    type_parameters = <
+        K: Clone + Debug + Eq + Hash + Send + Sync + rutenspitz::closures::Project,
+        V: Clone + Debug + Eq + Send + Sync
+    >,
+
+    methods {
+        equal {
+            fn contains_key(&self, k: &K) -> bool;
+            fn insert(&self, k: K, v: V) -> Option<V>;
+            fn remove(&self, k: &K) -> Option<(K, V)>;
+            fn remove_if(&self, k: &K, f: impl FnOnce(&K, &V) -> bool) -> Option<(K, V)>;
+        }
+    }
+}
+
+const THREADS: usize = 3;
+const MAX_OPS_PER_THREAD: usize = 8;
+
+fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut ring = Unstructured::new(data);
+    let capacity: u8 = Arbitrary::arbitrary(&mut ring)?;
+
+    let model = ModelDashMap::<u8, u16>::default();
+    let tested = DashMap::<u8, u16>::with_capacity(capacity as usize);
+
+    let mut threads: Vec<Vec<op::Op<u8, u16>>> = vec![vec![]; THREADS];
+    while !ring.is_empty() {
+        let thread = usize::from(<u8 as Arbitrary>::arbitrary(&mut ring)?) % THREADS;
+        let op = <op::Op<u8, u16> as Arbitrary>::arbitrary(&mut ring)?;
+        if threads[thread].len() < MAX_OPS_PER_THREAD {
+            threads[thread].push(op);
+        }
+    }
+
+    rutenspitz::linearizability::check(&model, &tested, &threads);
+
+    Ok(())
+}
+
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
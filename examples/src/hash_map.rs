@@ -1,33 +1,16 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::cast_possible_truncation)]
 #![allow(clippy::manual_find_map)]
 #![allow(clippy::must_use_candidate)]
 #![allow(clippy::option_if_let_else)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::hashing::FuzzBuildHasher;
 
 use hashbrown::HashMap;
 
 use std::fmt::Debug;
-use std::hash::{BuildHasher, Hash};
-
-pub struct BuildAHasher {
-    seed: u128,
-}
-
-impl BuildAHasher {
-    pub fn new(seed: u128) -> Self {
-        Self { seed }
-    }
-}
-
-impl BuildHasher for BuildAHasher {
-    type Hasher = ahash::AHasher;
-
-    fn build_hasher(&self) -> Self::Hasher {
-        ahash::RandomState::with_seed(self.seed as usize).build_hasher()
-    }
-}
+use std::hash::Hash;
 
 #[derive(Default)]
 pub struct ModelHashMap<K, V>
@@ -49,6 +32,10 @@ where
         self.data.iter().any(|probe| probe.0 == *k)
     }
 
+    pub fn entry(&mut self, k: K) -> ModelEntry<'_, K, V> {
+        ModelEntry { map: self, key: k }
+    }
+
     pub fn get(&self, k: &K) -> Option<&V> {
         self.data.iter().find(|probe| probe.0 == *k).map(|e| &e.1)
     }
@@ -100,52 +87,95 @@ where
         self.data.shrink_to_fit();
     }
 
-    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+    pub fn drain(&mut self) -> impl ExactSizeIterator<Item = (K, V)> + '_ {
         self.data.drain(..)
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (&K, &V)> {
         self.data.iter().map(|e| (&e.0, &e.1))
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+    pub fn iter_mut(&mut self) -> impl ExactSizeIterator<Item = (&K, &mut V)> {
         self.data.iter_mut().map(|e| (&e.0, &mut e.1))
     }
 
-    pub fn keys(&self) -> impl Iterator<Item = &K> {
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &K> {
         self.data.iter().map(|e| &e.0)
     }
 
-    pub fn values(&self) -> impl Iterator<Item = &V> {
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &V> {
         self.data.iter().map(|e| &e.1)
     }
 
-    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+    pub fn values_mut(&mut self) -> impl ExactSizeIterator<Item = &mut V> {
         self.data.iter_mut().map(|e| &mut e.1)
     }
 }
 
-fn sort_iterator<T: Ord, I: Iterator<Item = T>>(i: I) -> Vec<T> {
-    let mut v: Vec<_> = i.collect::<Vec<_>>();
-    v.sort();
-    v
+pub struct ModelEntry<'a, K, V>
+where
+    K: Eq + Hash,
+{
+    map: &'a mut ModelHashMap<K, V>,
+    key: K,
+}
+
+impl<'a, K, V> ModelEntry<'a, K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn or_default(self) -> &'a mut V
+    where
+        V: Default,
+    {
+        self.or_insert(V::default())
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        let pos = self.map.data.iter().position(|probe| probe.0 == self.key);
+        let idx = pos.unwrap_or_else(|| {
+            self.map.data.push((self.key, default));
+            self.map.data.len() - 1
+        });
+        &mut self.map.data[idx].1
+    }
+
+    pub fn or_insert_with<F>(self, default: F) -> &'a mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let pos = self.map.data.iter().position(|probe| probe.0 == self.key);
+        let idx = pos.unwrap_or_else(|| {
+            self.map.data.push((self.key, default()));
+            self.map.data.len() - 1
+        });
+        &mut self.map.data[idx].1
+    }
 }
 
 arbitrary_stateful_operations! {
     model = ModelHashMap<K, V>,
-    tested = HashMap<K, V, BuildAHasher>,
+    tested = HashMap<K, V, FuzzBuildHasher>,
 
     type_parameters = <
         K: Clone + Debug + Eq + Hash + Ord,
-        V: Clone + Debug + Eq + Ord
+        V: Clone + Debug + Default + Eq + Ord
     >,
 
     methods {
         equal {
             fn clear(&mut self);
+            #[no_alloc]
             fn contains_key(&self, k: &K) -> bool;
+            #[no_alloc]
             fn get(&self, k: &K) -> Option<&V>;
+            #[no_alloc]
             fn get_key_value(&self, k: &K) -> Option<(&K, &V)>;
+            #[no_alloc]
             fn get_mut(&mut self, k: &K) -> Option<&mut V>;
             fn insert(&mut self, k: K, v: V) -> Option<V>;
             fn remove(&mut self, k: &K) -> Option<V>;
@@ -153,7 +183,7 @@ arbitrary_stateful_operations! {
             fn shrink_to_fit(&mut self);
         }
 
-        equal_with(sort_iterator) {
+        iterate_unordered(next, size_hint, len) {
             fn drain(&mut self) -> impl Iterator<Item = (K, V)>;
             fn iter(&self) -> impl Iterator<Item = (&K, &V)>;
             fn iter_mut(&self) -> impl Iterator<Item = (&K, &mut V)>;
@@ -161,6 +191,17 @@ arbitrary_stateful_operations! {
             fn values(&self) -> impl Iterator<Item = &V>;
             fn values_mut(&mut self) -> impl Iterator<Item = &mut V>;
         }
+
+        handle {
+            fn entry(&mut self, k: K) -> Entry<K, V> {
+                equal {
+                    fn key(&self) -> &K;
+                    fn or_default(self) -> &mut V;
+                    fn or_insert(self, default: V) -> &mut V;
+                    fn or_insert_with(self, default: impl FnOnce() -> V) -> &mut V;
+                }
+            }
+        }
     }
 
     pre {
@@ -186,27 +227,44 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut ring = Unstructured::new(data);
 
     let capacity: u16 = Arbitrary::arbitrary(&mut ring)?;
-    let hash_seed: u128 = Arbitrary::arbitrary(&mut ring)?;
+    let hasher: FuzzBuildHasher = Arbitrary::arbitrary(&mut ring)?;
 
     let mut model = ModelHashMap::<u16, u16>::default();
-    let mut tested: HashMap<u16, u16, BuildAHasher> =
-        HashMap::with_capacity_and_hasher(capacity as usize, BuildAHasher::new(hash_seed));
+    let mut tested: HashMap<u16, u16, FuzzBuildHasher> =
+        HashMap::with_capacity_and_hasher(capacity as usize, hasher.clone());
+
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start(
+        "hash_map",
+        format!(
+            "let mut v: HashMap<u16, u16, FuzzBuildHasher> =\n    HashMap::with_capacity_and_hasher({capacity}, {hasher});"
+        ),
+    );
 
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&op);
         op.execute_and_compare(&mut model, &mut tested);
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
+/// Replays an input, such as a crash file, for `hash_map_replay`.
+#[allow(dead_code)]
+pub(crate) fn replay(data: &[u8]) -> arbitrary::Result<rutenspitz::replay::Report> {
+    use arbitrary::Arbitrary;
+
+    op::replay(data, |ring| {
+        let capacity: u16 = Arbitrary::arbitrary(ring)?;
+        let hasher: FuzzBuildHasher = Arbitrary::arbitrary(ring)?;
+        Ok((
+            ModelHashMap::<u16, u16>::default(),
+            HashMap::with_capacity_and_hasher(capacity as usize, hasher),
+        ))
+    })
 }
+
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
@@ -0,0 +1,43 @@
+//! Replays crash files of the `hash_map` target, printing the numbered trace
+//! with the results of the model and the tested map side by side:
+//!
+//! ```sh
+//! cargo run --bin hash_map_replay -- hfuzz_workspace/hash_map/*.fuzz
+//! ```
+
+#[allow(dead_code, unused_attributes)]
+#[path = "hash_map.rs"]
+mod hash_map;
+
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut failed = false;
+    for path in std::env::args_os().skip(1) {
+        let path = Path::new(&path);
+        println!("{}:", path.display());
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(error) => {
+                println!("The file could not be read: {error}");
+                failed = true;
+                continue;
+            }
+        };
+        match hash_map::replay(&data) {
+            Ok(report) => {
+                print!("{report}");
+                failed |= report.failed_op().is_some();
+            }
+            Err(error) => println!("The input could not be decoded: {error}"),
+        }
+        println!();
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
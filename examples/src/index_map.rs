@@ -1,8 +1,8 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::manual_find_map)]
 #![allow(clippy::must_use_candidate)]
 #![allow(clippy::option_if_let_else)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
 
 use indexmap::IndexMap;
@@ -146,8 +146,8 @@ arbitrary_stateful_operations! {
             fn get(&self, k: &K) -> Option<&V>;
             fn get_full(&self, k: &K) -> Option<(usize, &K, &V)>;
             fn get_full_mut(&mut self, k: &K) -> Option<(usize, &K, &mut V)>;
-            fn get_index(&self, index: usize) -> Option<(&K, &V)>;
-            fn get_index_mut(&mut self, index: usize) -> Option<(&mut K, &mut V)>;
+            fn get_index(&self, #[strategy(0..16usize)] index: usize) -> Option<(&K, &V)>;
+            fn get_index_mut(&mut self, #[strategy(0..16usize)] index: usize) -> Option<(&mut K, &mut V)>;
             fn get_mut(&mut self, k: &K) -> Option<&mut V>;
             fn insert(&mut self, k: K, v: V) -> Option<V>;
             fn is_empty(&self) -> bool;
@@ -155,7 +155,7 @@ arbitrary_stateful_operations! {
             fn pop(&mut self) -> Option<(K, V)>;
             fn swap_remove(&mut self, key: &K) -> Option<V>;
             fn swap_remove_full(&mut self, key: &K) -> Option<(usize, K, V)>;
-            fn swap_remove_index(&mut self, index: usize) -> Option<(K, V)>;
+            fn swap_remove_index(&mut self, #[strategy(0..16usize)] index: usize) -> Option<(K, V)>;
         }
 
         equal_with(sort_iterator) {
@@ -177,21 +177,21 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut model = ModelHashMap::<u16, u16>::default();
     let mut tested = IndexMap::<u16, u16>::with_capacity(capacity as usize);
 
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start(
+        "index_map",
+        format!("let mut v = IndexMap::<u16, u16>::with_capacity({capacity});"),
+    );
+
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&op);
         op.execute_and_compare(&mut model, &mut tested);
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
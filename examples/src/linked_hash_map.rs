@@ -1,7 +1,7 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::manual_find_map)]
 #![allow(clippy::must_use_candidate)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
 
 use linked_hash_map::LinkedHashMap;
@@ -127,21 +127,21 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut model = ModelHashMap::<u16, u16>::default();
     let mut tested = LinkedHashMap::<u16, u16>::with_capacity(capacity as usize);
 
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start(
+        "linked_hash_map",
+        format!("let mut v = LinkedHashMap::<u16, u16>::with_capacity({capacity});"),
+    );
+
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u16, u16> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&op);
         op.execute_and_compare(&mut model, &mut tested);
     }
 
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
@@ -0,0 +1,103 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+#![allow(clippy::must_use_candidate)]
+
+use rutenspitz::arbitrary_stateful_operations;
+
+use loom::sync::atomic::{AtomicU16, Ordering};
+
+/// A register holding the greatest of the values written to it, updated
+/// with a compare-and-swap loop.
+#[derive(Default)]
+pub struct MaxRegister {
+    value: AtomicU16,
+}
+
+impl MaxRegister {
+    pub fn get(&self) -> u16 {
+        self.value.load(Ordering::Acquire)
+    }
+
+    pub fn raise(&self, value: u16) -> u16 {
+        let mut current = self.value.load(Ordering::Acquire);
+        while current < value {
+            match self.value.compare_exchange_weak(
+                current,
+                value,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(previous) => return previous,
+                Err(actual) => current = actual,
+            }
+        }
+        current
+    }
+
+    pub fn reset(&self) -> u16 {
+        self.value.swap(0, Ordering::AcqRel)
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModelMaxRegister {
+    value: u16,
+}
+
+impl ModelMaxRegister {
+    pub fn get(&self) -> u16 {
+        self.value
+    }
+
+    pub fn raise(&mut self, value: u16) -> u16 {
+        let previous = self.value;
+        self.value = self.value.max(value);
+        previous
+    }
+
+    pub fn reset(&mut self) -> u16 {
+        std::mem::take(&mut self.value)
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = ModelMaxRegister,
+    tested = MaxRegister,
+    concurrent = true,
+
+    methods {
+        equal {
+            fn get(&self) -> u16;
+            fn raise(&self, value: u16) -> u16;
+            fn reset(&self) -> u16;
+        }
+    }
+}
+
+// Loom explores every interleaving, so the schedules are kept short.
+const THREADS: usize = 2;
+const MAX_OPS_PER_THREAD: usize = 3;
+
+fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut ring = Unstructured::new(data);
+
+    let mut threads: Vec<Vec<op::Op>> = vec![vec![]; THREADS];
+    while !ring.is_empty() {
+        let thread = usize::from(<u8 as Arbitrary>::arbitrary(&mut ring)?) % THREADS;
+        let op = <op::Op as Arbitrary>::arbitrary(&mut ring)?;
+        if threads[thread].len() < MAX_OPS_PER_THREAD {
+            threads[thread].push(op);
+        }
+    }
+
+    rutenspitz::linearizability::loom::check(
+        ModelMaxRegister::default(),
+        MaxRegister::default,
+        threads,
+    );
+
+    Ok(())
+}
+
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
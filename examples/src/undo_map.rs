@@ -0,0 +1,189 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+#![allow(clippy::must_use_candidate)]
+
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::transaction::Savepoints;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// A map recording the previous value of every key it changes, so that the
+/// changes made since a savepoint can be undone.
+pub struct UndoMap<K, V> {
+    map: HashMap<K, V>,
+    log: Vec<(K, Option<V>)>,
+    /// The open savepoints, along with the length of the log at each.
+    savepoints: Vec<(usize, usize)>,
+    next_savepoint: usize,
+}
+
+impl<K, V> UndoMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            log: Vec::new(),
+            savepoints: Vec::new(),
+            next_savepoint: 0,
+        }
+    }
+
+    pub fn get(&self, k: &K) -> Option<&V> {
+        self.map.get(k)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let previous = self.map.insert(k.clone(), v);
+        self.log.push((k, previous.clone()));
+        previous
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let previous = self.map.remove(k);
+        if previous.is_some() {
+            self.log.push((k.clone(), previous.clone()));
+        }
+        previous
+    }
+
+    pub fn savepoint(&mut self) -> usize {
+        let savepoint = self.next_savepoint;
+        self.next_savepoint += 1;
+        self.savepoints.push((savepoint, self.log.len()));
+        savepoint
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the savepoint has been rolled back or committed.
+    pub fn rollback(&mut self, savepoint: usize) {
+        let index = self
+            .savepoints
+            .iter()
+            .position(|&(probe, _)| probe == savepoint)
+            .expect("the savepoint is gone");
+        let (_, len) = self.savepoints.drain(index..).next().unwrap();
+        for (k, previous) in self.log.drain(len..).rev() {
+            match previous {
+                Some(v) => self.map.insert(k, v),
+                None => self.map.remove(&k),
+            };
+        }
+    }
+
+    pub fn commit(&mut self) {
+        self.log.clear();
+        self.savepoints.clear();
+    }
+}
+
+impl<K, V> Default for UndoMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ModelMap<K, V> {
+    data: Vec<(K, V)>,
+}
+
+impl<K, V> ModelMap<K, V>
+where
+    K: Eq,
+{
+    pub fn get(&self, k: &K) -> Option<&V> {
+        self.data.iter().find(|probe| probe.0 == *k).map(|e| &e.1)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if let Some(entry) = self.data.iter_mut().find(|probe| probe.0 == k) {
+            Some(std::mem::replace(&mut entry.1, v))
+        } else {
+            self.data.push((k, v));
+            None
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let pos = self.data.iter().position(|probe| probe.0 == *k);
+        pos.map(|idx| self.data.swap_remove(idx).1)
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = Savepoints<ModelMap<K, V>, usize>,
+    tested = UndoMap<K, V>,
+
+    type_parameters = <
+        K: Clone + Debug + Eq + Hash,
+        V: Clone + Debug + Eq
+    >,
+
+    methods {
+        equal {
+            fn get(&self, k: &K) -> Option<&V>;
+            fn insert(&mut self, k: K, v: V) -> Option<V>;
+            fn is_empty(&self) -> bool;
+            fn len(&self) -> usize;
+            fn remove(&mut self, k: &K) -> Option<V>;
+        }
+
+        transactional {
+            savepoint fn savepoint(&mut self) -> usize;
+            rollback fn rollback(&mut self, savepoint: usize);
+            commit fn commit(&mut self);
+        }
+    }
+}
+
+fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut ring = Unstructured::new(data);
+
+    let mut model = Savepoints::new(ModelMap::<u8, u16>::default());
+    let mut tested = UndoMap::<u8, u16>::new();
+
+    #[cfg(fuzzing_debug)]
+    rutenspitz::regression::start(
+        "undo_map",
+        "let mut v = UndoMap::<u8, u16>::new();\nlet mut savepoints = rutenspitz::transaction::Ids::new();",
+    );
+
+    let mut op_trace = String::new();
+    while !ring.is_empty() {
+        let op = <op::Op<u8, u16> as Arbitrary>::arbitrary(&mut ring)?;
+        op.append_to_trace(&mut op_trace);
+        rutenspitz::regression::push(&op);
+        op.execute_and_compare(&mut model, &mut tested);
+    }
+
+    Ok(())
+}
+
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
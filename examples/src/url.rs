@@ -1,6 +1,7 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 #![allow(clippy::let_unit_value)]
+#![allow(clippy::ref_option)]
 
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
 
 use std::fmt::Debug;
@@ -26,7 +27,7 @@ impl UrlFix for url::Url {
     }
 
     fn set_query_(&mut self, query: &Option<String>) {
-        let _ = self.set_query(query.as_ref().map(String::as_str));
+        self.set_query(query.as_ref().map(String::as_str));
     }
 }
 
@@ -81,7 +82,6 @@ arbitrary_stateful_operations! {
     }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     use arbitrary::{Arbitrary, Unstructured};
 
@@ -90,7 +90,8 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut tested = url::Url::parse("https://example.org").unwrap();
 
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
         op.execute(&mut tested);
     }
@@ -98,12 +99,4 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
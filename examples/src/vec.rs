@@ -1,5 +1,5 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
 //#![allow(clippy::let_unit_value)]
-use honggfuzz::fuzz;
 use rutenspitz::arbitrary_stateful_operations;
 
 arbitrary_stateful_operations! {
@@ -15,7 +15,6 @@ arbitrary_stateful_operations! {
     }
 }
 
-#[allow(clippy::unnecessary_wraps)]
 fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     use arbitrary::{Arbitrary, Unstructured};
 
@@ -24,7 +23,8 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     let mut tested = Vec::<u32>::new();
 
     let mut op_trace = String::new();
-    while let Ok(op) = <op::Op<u32> as Arbitrary>::arbitrary(&mut ring) {
+    while !ring.is_empty() {
+        let op = <op::Op<u32> as Arbitrary>::arbitrary(&mut ring)?;
         op.append_to_trace(&mut op_trace);
         op.execute(&mut tested);
     }
@@ -32,12 +32,4 @@ fn fuzz_cycle(data: &[u8]) -> arbitrary::Result<()> {
     Ok(())
 }
 
-fn main() -> Result<(), ()> {
-    better_panic::install();
-
-    loop {
-        fuzz!(|data: &[u8]| {
-            let _ = fuzz_cycle(data);
-        });
-    }
-}
+rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
@@ -0,0 +1,188 @@
+//! Counting the allocations of the tested instance, with the `allocations`
+//! feature.
+//!
+//! [`Counting`] wraps the global allocator and counts the allocations and
+//! the deallocations made while the generated code calls the methods of the
+//! tested instance, but not the operations on handles, which the generated
+//! code collects the items of. Only those made on the calling thread are
+//! counted, so the work a method hands over to other threads, or that
+//! other threads do meanwhile, is left out. The counts of the
+//! operation being executed are returned by [`op`], which the `post` block
+//! can assert on:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: rutenspitz::allocations::Counting = rutenspitz::allocations::Counting::system();
+//!
+//! arbitrary_stateful_operations! {
+//!     ...
+//!     post {
+//!         if op_name == "clear" {
+//!             assert_eq!(rutenspitz::allocations::op().deallocations, 0);
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The methods given `#[no_alloc]` in the specification fail if they
+//! allocate. Nothing is counted unless [`Counting`] is the global allocator.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The allocations and the deallocations made. A reallocation counts as
+/// both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub allocated_bytes: u64,
+    pub deallocated_bytes: u64,
+}
+
+thread_local! {
+    // Whether the tested instance is being called on the thread.
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static OP: Cell<Counts> = const {
+        Cell::new(Counts {
+            allocations: 0,
+            deallocations: 0,
+            allocated_bytes: 0,
+            deallocated_bytes: 0,
+        })
+    };
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether [`Counting`] is the global allocator, as soon as it allocated.
+#[must_use]
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// The counts of the calls of the tested instance by the operation being
+/// executed on the thread, or the last one.
+#[must_use]
+pub fn op() -> Counts {
+    OP.with(Cell::get)
+}
+
+/// Starts counting for another operation. Called by the generated code.
+#[doc(hidden)]
+pub fn start_op() {
+    OP.with(|op| op.set(Counts::default()));
+}
+
+/// Counts while alive. Held by the generated code over the calls of the
+/// tested instance.
+#[doc(hidden)]
+pub struct Tracking {
+    previous: bool,
+}
+
+impl Tracking {
+    #[must_use]
+    pub fn start() -> Self {
+        Self {
+            previous: TRACKING.with(|tracking| tracking.replace(true)),
+        }
+    }
+}
+
+impl Drop for Tracking {
+    fn drop(&mut self) {
+        TRACKING.with(|tracking| tracking.set(self.previous));
+    }
+}
+
+/// Fails if the operation allocated, for the methods given `#[no_alloc]`.
+/// Called by the generated code.
+#[doc(hidden)]
+pub fn forbid(op_index: usize, op_name: &'static str) {
+    let counts = op();
+    if counts.allocations > 0 {
+        crate::failure::fail(crate::Failure::InvariantViolation {
+            op_index,
+            op_name,
+            message: format!(
+                "the method allocated {} times, {} bytes, despite `#[no_alloc]`",
+                counts.allocations, counts.allocated_bytes
+            ),
+        });
+    }
+}
+
+// Never allocates, nor panics if the thread-local storage is gone.
+fn count(allocated: Option<usize>, deallocated: Option<usize>) {
+    let _ = TRACKING.try_with(|tracking| {
+        if !tracking.get() {
+            return;
+        }
+        let _ = OP.try_with(|op| {
+            let mut counts = op.get();
+            if let Some(size) = allocated {
+                counts.allocations += 1;
+                counts.allocated_bytes += size as u64;
+            }
+            if let Some(size) = deallocated {
+                counts.deallocations += 1;
+                counts.deallocated_bytes += size as u64;
+            }
+            op.set(counts);
+        });
+    });
+}
+
+/// A global allocator counting the allocations of the tested instance,
+/// wrapping another one.
+#[derive(Debug, Default)]
+pub struct Counting<A = System> {
+    inner: A,
+}
+
+impl Counting<System> {
+    /// Wraps the system allocator.
+    #[must_use]
+    pub const fn system() -> Self {
+        Self { inner: System }
+    }
+}
+
+impl<A> Counting<A> {
+    /// Wraps another allocator.
+    #[must_use]
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+// SAFETY: the calls are forwarded to the inner allocator as they are.
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        INSTALLED.store(true, Ordering::Relaxed);
+        count(Some(layout.size()), None);
+        // SAFETY: the caller upholds the contract of `alloc`.
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        INSTALLED.store(true, Ordering::Relaxed);
+        count(Some(layout.size()), None);
+        // SAFETY: the caller upholds the contract of `alloc_zeroed`.
+        unsafe { self.inner.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count(None, Some(layout.size()));
+        // SAFETY: the caller upholds the contract of `dealloc`.
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count(Some(new_size), Some(layout.size()));
+        // SAFETY: the caller upholds the contract of `realloc`.
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
+    }
+}
@@ -0,0 +1,197 @@
+//! Checking a specification without a fuzzer, such as in `cargo test`.
+//!
+//! [`check`] fills byte buffers from a pseudorandom generator, decodes the
+//! model, the tested instance and a sequence of operations from each of them
+//! the way the fuzzing loop does, and runs them. The buffers only depend on
+//! the seed, so a failure is reported along with the seed of the buffer and
+//! the trace of its replay, and checking with that seed and a single case
+//! reproduces it.
+//!
+//! ```ignore
+//! use rutenspitz::check::Config;
+//!
+//! #[test]
+//! fn hash_map() {
+//!     let config = Config { cases: 1000, max_ops: 64, seed: 0 };
+//!     rutenspitz::check::<op::Op<u16, u16>>(config)
+//!         .run(|ring| Ok((ModelHashMap::default(), HashMap::with_capacity(ring.arbitrary()?))))
+//!         .unwrap();
+//! }
+//! ```
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::elements;
+use crate::failure;
+use crate::replay::{self, Report};
+use crate::Operation;
+
+/// How many bytes a buffer has at most for every operation.
+const BYTES_PER_OP: usize = 16;
+
+/// What to check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// How many buffers to run.
+    pub cases: u32,
+    /// How many operations to decode from a buffer at most.
+    pub max_ops: usize,
+    /// The seed of the first buffer, the next ones follow.
+    pub seed: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cases: 256,
+            max_ops: 64,
+            seed: 0,
+        }
+    }
+}
+
+/// A buffer that failed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Failed {
+    /// The seed of the buffer.
+    pub seed: u64,
+    /// The report of its replay.
+    pub report: Report,
+}
+
+impl fmt::Display for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "The case with the seed {} failed:", self.seed)?;
+        write!(f, "{}", self.report)?;
+        write!(f, "Check with `seed: {}` and `cases: 1` to reproduce it.", self.seed)
+    }
+}
+
+// Like `Display`, so that unwrapping the result of a test prints the report.
+impl fmt::Debug for Failed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// A check of the operations of type `O`, see [`check`].
+#[derive(Clone, Copy, Debug)]
+pub struct Check<O> {
+    config: Config,
+    ops: PhantomData<fn() -> O>,
+}
+
+/// Starts a check of the operations of type `O`, with the given
+/// configuration. [`Check::run`] runs it.
+#[must_use]
+pub fn check<O>(config: Config) -> Check<O> {
+    Check {
+        config,
+        ops: PhantomData,
+    }
+}
+
+impl<O> Check<O>
+where
+    O: for<'a> Arbitrary<'a>,
+{
+    /// Runs the cases, with `new` building the model and the tested instance
+    /// from the start of every buffer. The buffers `new` fails on are
+    /// skipped.
+    ///
+    /// The panics are silenced meanwhile, on all the threads since the panic
+    /// hook is global to the process, and no regression test is written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the report of the first buffer failing.
+    pub fn run<M, T, F>(self, mut new: F) -> Result<(), Failed>
+    where
+        O: Operation<M, T>,
+        F: FnMut(&mut Unstructured<'_>) -> arbitrary::Result<(M, T)>,
+    {
+        let Config { cases, max_ops, seed } = self.config;
+        failure::silently(|| {
+            for case in 0..u64::from(cases) {
+                let seed = seed.wrapping_add(case);
+                let data = buffer(seed, max_ops);
+                failure::reset_op_index();
+                let Some((mut model, mut tested, ops)) = decode::<O, _, _>(&data, max_ops, &mut new) else {
+                    continue;
+                };
+                let result = catch_unwind(AssertUnwindSafe(move || {
+                    for op in ops {
+                        op.execute_and_compare(&mut model, &mut tested);
+                    }
+                    drop((model, tested));
+                    elements::check_drops();
+                }));
+                if result.is_err() {
+                    let Some((model, tested, ops)) = decode::<O, _, _>(&data, max_ops, &mut new) else {
+                        continue;
+                    };
+                    let report = replay::replay(ops, model, tested);
+                    return Err(Failed { seed, report });
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Decodes the model, the tested instance and up to `max_ops` operations
+/// from a buffer, like the fuzzing loop.
+fn decode<O, M, T>(
+    data: &[u8],
+    max_ops: usize,
+    new: &mut impl FnMut(&mut Unstructured<'_>) -> arbitrary::Result<(M, T)>,
+) -> Option<(M, T, Vec<O>)>
+where
+    O: for<'a> Arbitrary<'a>,
+{
+    let mut ring = Unstructured::new(data);
+    let (model, tested) = new(&mut ring).ok()?;
+    let mut ops = vec![];
+    while !ring.is_empty() && ops.len() < max_ops {
+        match O::arbitrary(&mut ring) {
+            Ok(op) => ops.push(op),
+            Err(_) => break,
+        }
+    }
+    Some((model, tested, ops))
+}
+
+/// The buffer of a seed, of a random length up to `BYTES_PER_OP` bytes for
+/// every operation.
+fn buffer(seed: u64, max_ops: usize) -> Vec<u8> {
+    let mut rng = SplitMix64(seed);
+    let max_len = max_ops.saturating_mul(BYTES_PER_OP);
+    #[allow(clippy::cast_possible_truncation)]
+    let len = (rng.next() % (max_len as u64 + 1)) as usize;
+    let mut data = Vec::with_capacity(len + 8);
+    while data.len() < len {
+        data.extend_from_slice(&rng.next().to_le_bytes());
+    }
+    data.truncate(len);
+    data
+}
+
+/// The `SplitMix64` generator, which is good enough to fill the buffers and
+/// saves a dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
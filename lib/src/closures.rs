@@ -0,0 +1,358 @@
+//! Closure arguments drawn from a finite family of shapes.
+//!
+//! An argument of type `impl FnMut(&K, &mut V) -> bool` (or any other `Fn*`
+//! trait) in the DSL is generated as a [`Closure`] whose shape depends on the
+//! return type: a [`Predicate`] for `bool`, a [`Comparator`] for `Ordering`,
+//! a [`Mutator`] of the first argument for `()` and a [`Mapper`] otherwise.
+//! Predicates, comparators and mappers look at their arguments through
+//! [`Project`], and are printed calling [`project`] so that the closure in a
+//! trace compiles and runs the same for any argument type.
+
+use arbitrary::Arbitrary;
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// Projects a value onto the integer the generated closures operate on.
+pub trait Project {
+    fn project(&self) -> u64;
+}
+
+macro_rules! impl_project {
+    ($($ty:ty),*) => {
+        $(
+            impl Project for $ty {
+                #[allow(clippy::cast_lossless, clippy::cast_sign_loss)]
+                fn project(&self) -> u64 {
+                    *self as u64
+                }
+            }
+        )*
+    };
+}
+
+impl_project!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, bool, char);
+
+impl Project for () {
+    fn project(&self) -> u64 {
+        0
+    }
+}
+
+impl<T: Project + ?Sized> Project for &T {
+    fn project(&self) -> u64 {
+        (**self).project()
+    }
+}
+
+impl<T: Project + ?Sized> Project for &mut T {
+    fn project(&self) -> u64 {
+        (**self).project()
+    }
+}
+
+/// The projection of an argument, as the printed closures compute it.
+#[must_use]
+pub fn project<A: Project + ?Sized>(a: &A) -> u64 {
+    a.project()
+}
+
+/// How the printed closures call [`project`].
+pub(crate) const PROJECT: &str = "rutenspitz::closures::project";
+
+/// A shape of a closure that can be written out as Rust code.
+pub trait Shape {
+    /// The number of leading parameters the body refers to.
+    fn used_params(&self) -> usize;
+
+    /// Writes out the body of the closure given the names of its parameters.
+    ///
+    /// # Errors
+    ///
+    /// Fails if writing to the formatter does.
+    fn fmt_body(&self, f: &mut fmt::Formatter<'_>, params: &[&str]) -> fmt::Result;
+}
+
+/// A predicate over the projection of the first argument.
+#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Predicate {
+    Constant(bool),
+    Modulo { divisor: u8, remainder: u8 },
+    LessThan(u16),
+}
+
+impl Predicate {
+    fn test(&self, value: u64) -> bool {
+        match *self {
+            Self::Constant(result) => result,
+            Self::Modulo { divisor, remainder } => {
+                value % u64::from(divisor.max(1)) == u64::from(remainder)
+            }
+            Self::LessThan(threshold) => value < u64::from(threshold),
+        }
+    }
+}
+
+impl Shape for Predicate {
+    fn used_params(&self) -> usize {
+        match self {
+            Self::Constant(_) => 0,
+            Self::Modulo { .. } | Self::LessThan(_) => 1,
+        }
+    }
+
+    fn fmt_body(&self, f: &mut fmt::Formatter<'_>, params: &[&str]) -> fmt::Result {
+        match *self {
+            Self::Constant(result) => write!(f, "{result}"),
+            Self::Modulo { divisor, remainder } => write!(
+                f,
+                "{PROJECT}(&{}) % {} == {}",
+                params[0],
+                divisor.max(1),
+                remainder
+            ),
+            Self::LessThan(threshold) => write!(f, "{PROJECT}(&{}) < {}", params[0], threshold),
+        }
+    }
+}
+
+/// A comparison of the projections of the first two arguments.
+#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparator {
+    Ascending,
+    Descending,
+    ModuloAscending(u8),
+    Equal,
+}
+
+impl Comparator {
+    fn compare(&self, a: u64, b: u64) -> Ordering {
+        match *self {
+            Self::Ascending => a.cmp(&b),
+            Self::Descending => b.cmp(&a),
+            Self::ModuloAscending(divisor) => {
+                let divisor = u64::from(divisor.max(1));
+                (a % divisor).cmp(&(b % divisor))
+            }
+            Self::Equal => Ordering::Equal,
+        }
+    }
+}
+
+impl Shape for Comparator {
+    fn used_params(&self) -> usize {
+        match self {
+            Self::Equal => 0,
+            _ => 2,
+        }
+    }
+
+    fn fmt_body(&self, f: &mut fmt::Formatter<'_>, params: &[&str]) -> fmt::Result {
+        match *self {
+            Self::Ascending => write!(f, "{PROJECT}(&{}).cmp(&{PROJECT}(&{}))", params[0], params[1]),
+            Self::Descending => write!(f, "{PROJECT}(&{}).cmp(&{PROJECT}(&{}))", params[1], params[0]),
+            Self::ModuloAscending(divisor) => write!(
+                f,
+                "({PROJECT}(&{a}) % {d}).cmp(&({PROJECT}(&{b}) % {d}))",
+                a = params[0],
+                b = params[1],
+                d = divisor.max(1)
+            ),
+            Self::Equal => write!(f, "std::cmp::Ordering::Equal"),
+        }
+    }
+}
+
+/// A map from the projection of the first argument to a constant from a
+/// table.
+#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mapper<R> {
+    Constant(R),
+    Lookup(R, Vec<R>),
+}
+
+impl<R: Clone> Mapper<R> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn map(&self, value: u64) -> R {
+        match self {
+            Self::Constant(result) => result.clone(),
+            Self::Lookup(first, rest) => {
+                let index = (value % (rest.len() as u64 + 1)) as usize;
+                index
+                    .checked_sub(1)
+                    .map_or(first, |index| &rest[index])
+                    .clone()
+            }
+        }
+    }
+}
+
+impl<R: fmt::Debug> Shape for Mapper<R> {
+    fn used_params(&self) -> usize {
+        match self {
+            Self::Constant(_) => 0,
+            Self::Lookup(..) => 1,
+        }
+    }
+
+    fn fmt_body(&self, f: &mut fmt::Formatter<'_>, params: &[&str]) -> fmt::Result {
+        match self {
+            Self::Constant(result) => write!(f, "{result:?}"),
+            Self::Lookup(first, rest) => {
+                write!(f, "[{first:?}")?;
+                for result in rest {
+                    write!(f, ", {result:?}")?;
+                }
+                match params.first() {
+                    Some(param) => write!(f, "][({PROJECT}(&{param}) % {}) as usize]", rest.len() + 1),
+                    None => write!(f, "][0]"),
+                }
+            }
+        }
+    }
+}
+
+/// A modification of the first argument, which must be a `&mut T`.
+#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mutator<T> {
+    Keep,
+    Set(T),
+}
+
+impl<T: fmt::Debug> Shape for Mutator<T> {
+    fn used_params(&self) -> usize {
+        match self {
+            Self::Keep => 0,
+            Self::Set(_) => 1,
+        }
+    }
+
+    fn fmt_body(&self, f: &mut fmt::Formatter<'_>, params: &[&str]) -> fmt::Result {
+        match self {
+            Self::Keep => write!(f, "{{}}"),
+            Self::Set(value) => write!(f, "*{} = {:?}", params[0], value),
+        }
+    }
+}
+
+/// A generated closure: a shape and, optionally, the index of the call
+/// (counting from zero) on which it panics.
+#[derive(Arbitrary, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Closure<S> {
+    pub shape: S,
+    pub panic_on_call: Option<u8>,
+}
+
+impl<S> Closure<S> {
+    /// A fresh instance of the closure with its own count of calls.
+    pub fn instance(&self) -> Instance<'_, S> {
+        Instance {
+            closure: self,
+            calls: Cell::new(0),
+        }
+    }
+}
+
+impl<S: Shape> Closure<S> {
+    /// Writes out the closure as Rust code, given the number of its
+    /// parameters.
+    pub fn display(&self, arity: usize) -> impl fmt::Display + '_ {
+        ClosureDisplay {
+            closure: self,
+            arity,
+        }
+    }
+}
+
+struct ClosureDisplay<'c, S> {
+    closure: &'c Closure<S>,
+    arity: usize,
+}
+
+impl<S: Shape> fmt::Display for ClosureDisplay<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 4] = ["a", "b", "c", "d"];
+
+        let used_params = self.closure.shape.used_params().min(self.arity);
+        let params: Vec<_> = (0..self.arity)
+            .map(|index| {
+                if index < used_params {
+                    NAMES.get(index).copied().unwrap_or("x")
+                } else {
+                    "_"
+                }
+            })
+            .collect();
+
+        if let Some(call) = self.closure.panic_on_call {
+            write!(
+                f,
+                "{{ let calls = std::cell::Cell::new(0); move |{}| {{ let n = calls.get(); \
+                 calls.set(n + 1); if n == {} {{ panic!() }} ",
+                params.join(", "),
+                call
+            )?;
+            self.closure.shape.fmt_body(f, &params)?;
+            write!(f, " }} }}")
+        } else {
+            write!(f, "|{}| ", params.join(", "))?;
+            self.closure.shape.fmt_body(f, &params)
+        }
+    }
+}
+
+/// An instance of a [`Closure`] passed to a single call of a method.
+pub struct Instance<'c, S> {
+    closure: &'c Closure<S>,
+    calls: Cell<u32>,
+}
+
+impl<S> Instance<'_, S> {
+    fn count_call(&self) {
+        let call = self.calls.get();
+        self.calls.set(call + 1);
+        if self.closure.panic_on_call.map(u32::from) == Some(call) {
+            crate::hook::deliberate();
+            #[allow(clippy::panic)]
+            {
+                panic!("the generated closure panicked on call {call}");
+            }
+        }
+    }
+}
+
+impl Instance<'_, Predicate> {
+    pub fn call<A: Project + ?Sized>(&self, a: &A) -> bool {
+        self.count_call();
+        self.closure.shape.test(a.project())
+    }
+}
+
+impl Instance<'_, Comparator> {
+    pub fn call<A: Project + ?Sized>(&self, a: &A, b: &A) -> Ordering {
+        self.count_call();
+        self.closure.shape.compare(a.project(), b.project())
+    }
+}
+
+impl<R: Clone> Instance<'_, Mapper<R>> {
+    pub fn call<A: Project + ?Sized>(&self, a: &A) -> R {
+        self.count_call();
+        self.closure.shape.map(a.project())
+    }
+}
+
+impl<T: Clone> Instance<'_, Mutator<T>> {
+    pub fn call(&self, a: &mut T) {
+        self.count_call();
+        if let Mutator::Set(ref value) = self.closure.shape {
+            a.clone_from(value);
+        }
+    }
+}
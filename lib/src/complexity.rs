@@ -0,0 +1,301 @@
+//! Checking the growth of the cost of the operations, with the `complexity`
+//! feature.
+//!
+//! A structure can behave like the model and still be quadratic by
+//! accident. The methods given a bound in the specification, such as
+//! `#[complexity(log n)]`, have the cost of every call on the tested
+//! instance recorded against the size of the model before it, `model.len()`
+//! unless the specification gives another with `size = <expression>`.
+//! [`report`] fits how the cost of every operation grows beyond its bound.
+//!
+//! The cost is the number of [`tick`]s during the call, such as the
+//! comparisons of [`Counted`] elements, which is deterministic, and the time
+//! it took for the operations that never tick, which is noisy and needs more
+//! calls.
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use crate::minimize::Shrink;
+use crate::trace::{self, FromTrace};
+
+/// How many calls of sizes of the same power of two a fit needs at least.
+pub const MIN_CALLS: u64 = 8;
+
+/// How many powers of two the sizes of the calls of a fit have to span.
+pub const MIN_SPAN: u32 = 3;
+
+/// How much faster than the bound the cost may grow before an operation
+/// exceeds it, as a power of the size.
+pub const TOLERANCE: f64 = 0.5;
+
+/// The bound of the cost of an operation, as a function of the size `n`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Constant,
+    Log,
+    Linear,
+    LinearLog,
+    Quadratic,
+}
+
+impl Bound {
+    #[allow(clippy::cast_precision_loss)]
+    fn cost(self, size: f64) -> f64 {
+        let n = size + 1.0;
+        match self {
+            Self::Constant => 1.0,
+            Self::Log => (n + 1.0).log2(),
+            Self::Linear => n,
+            Self::LinearLog => n * (n + 1.0).log2(),
+            Self::Quadratic => n * n,
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Constant => "O(1)",
+            Self::Log => "O(log n)",
+            Self::Linear => "O(n)",
+            Self::LinearLog => "O(n log n)",
+            Self::Quadratic => "O(n^2)",
+        })
+    }
+}
+
+thread_local! {
+    static TICKS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Counts an elementary step of the tested instance, such as a comparison.
+pub fn tick() {
+    TICKS.with(|ticks| ticks.set(ticks.get().wrapping_add(1)));
+}
+
+/// The calls of an operation with sizes of the same power of two.
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    calls: u64,
+    sizes: u64,
+    ticks: u64,
+    nanos: u64,
+}
+
+struct Samples {
+    bound: Bound,
+    buckets: BTreeMap<u32, Bucket>,
+}
+
+static SAMPLES: Mutex<BTreeMap<&'static str, Samples>> = Mutex::new(BTreeMap::new());
+
+/// A call of the tested instance being measured. Used by the generated code.
+#[doc(hidden)]
+pub struct Measurement {
+    ticks: u64,
+    start: Instant,
+}
+
+impl Measurement {
+    #[must_use]
+    pub fn start() -> Self {
+        Self {
+            ticks: TICKS.with(Cell::get),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn finish(self, op_name: &'static str, size: usize, bound: Bound) {
+        let nanos = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        let ticks = TICKS.with(Cell::get).wrapping_sub(self.ticks);
+        let size = size as u64;
+        let mut samples = SAMPLES.lock().unwrap_or_else(PoisonError::into_inner);
+        let samples = samples.entry(op_name).or_insert_with(|| Samples {
+            bound,
+            buckets: BTreeMap::new(),
+        });
+        let bucket = samples.buckets.entry(size.checked_ilog2().map_or(0, |log| log + 1)).or_default();
+        bucket.calls += 1;
+        bucket.sizes = bucket.sizes.saturating_add(size);
+        bucket.ticks = bucket.ticks.saturating_add(ticks);
+        bucket.nanos = bucket.nanos.saturating_add(nanos);
+    }
+}
+
+/// What the cost of the calls was measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Proxy {
+    Ticks,
+    Time,
+}
+
+/// How the cost of an operation grows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fit {
+    pub op_name: &'static str,
+    pub bound: Bound,
+    pub calls: u64,
+    pub proxy: Proxy,
+    /// How much faster than the bound the cost grows, as the power of the
+    /// size fitted to the calls, if they span enough sizes.
+    pub excess: Option<f64>,
+}
+
+impl Fit {
+    /// Whether the cost grows faster than the bound allows.
+    #[must_use]
+    pub fn exceeds(&self) -> bool {
+        self.excess.is_some_and(|excess| excess > TOLERANCE)
+    }
+}
+
+impl fmt::Display for Fit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let proxy = match self.proxy {
+            Proxy::Ticks => "ticks",
+            Proxy::Time => "time",
+        };
+        write!(f, "`{}` ({}, {} calls, by {proxy}): ", self.op_name, self.bound, self.calls)?;
+        match self.excess {
+            None => write!(f, "the sizes do not span enough to fit the growth"),
+            Some(excess) if self.exceeds() => write!(f, "exceeded, by a factor of n^{excess:.2}"),
+            Some(excess) => write!(f, "within, by a factor of n^{excess:.2}"),
+        }
+    }
+}
+
+/// Fits the growth of the cost of every operation measured so far, beyond
+/// its bound.
+#[must_use]
+pub fn report() -> Vec<Fit> {
+    let samples = SAMPLES.lock().unwrap_or_else(PoisonError::into_inner);
+    samples
+        .iter()
+        .map(|(&op_name, samples)| fit(op_name, samples))
+        .collect()
+}
+
+/// Forgets the calls measured so far.
+pub fn reset() {
+    SAMPLES.lock().unwrap_or_else(PoisonError::into_inner).clear();
+}
+
+/// Fits the logarithm of the mean cost over the bound against that of the
+/// mean size, by least squares, over the buckets with enough calls.
+#[allow(clippy::cast_precision_loss)]
+fn fit(op_name: &'static str, samples: &Samples) -> Fit {
+    let calls = samples.buckets.values().map(|bucket| bucket.calls).sum();
+    let proxy = if samples.buckets.values().any(|bucket| bucket.ticks > 0) {
+        Proxy::Ticks
+    } else {
+        Proxy::Time
+    };
+    let points: Vec<(u32, f64, f64)> = samples
+        .buckets
+        .iter()
+        .filter(|(_, bucket)| bucket.calls >= MIN_CALLS)
+        .map(|(&index, bucket)| {
+            let calls = bucket.calls as f64;
+            let size = bucket.sizes as f64 / calls;
+            let cost = match proxy {
+                Proxy::Ticks => bucket.ticks,
+                Proxy::Time => bucket.nanos,
+            } as f64
+                / calls;
+            (index, (size + 1.0).ln(), ((cost + 1.0) / samples.bound.cost(size)).ln())
+        })
+        .collect();
+
+    let span = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => last.0 - first.0,
+        _ => 0,
+    };
+    let excess = (span >= MIN_SPAN).then(|| {
+        let count = points.len() as f64;
+        let mean_x = points.iter().map(|point| point.1).sum::<f64>() / count;
+        let mean_y = points.iter().map(|point| point.2).sum::<f64>() / count;
+        let covariance: f64 = points.iter().map(|point| (point.1 - mean_x) * (point.2 - mean_y)).sum();
+        let variance: f64 = points.iter().map(|point| (point.1 - mean_x).powi(2)).sum();
+        covariance / variance
+    });
+
+    Fit {
+        op_name,
+        bound: samples.bound,
+        calls,
+        proxy,
+        excess,
+    }
+}
+
+/// An element that ticks on every comparison and every hash, which makes
+/// the number of those the deterministic cost of an operation.
+#[derive(Clone, Copy, Default)]
+pub struct Counted<T>(pub T);
+
+impl<T: PartialEq> PartialEq for Counted<T> {
+    fn eq(&self, other: &Self) -> bool {
+        tick();
+        self.0 == other.0
+    }
+}
+
+impl<T: Eq> Eq for Counted<T> {}
+
+impl<T: PartialOrd> PartialOrd for Counted<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        tick();
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<T: Ord> Ord for Counted<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        tick();
+        self.0.cmp(&other.0)
+    }
+}
+
+impl<T: Hash> Hash for Counted<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        tick();
+        self.0.hash(state);
+    }
+}
+
+// Like the value, so that the traces read the same.
+impl<T: fmt::Debug> fmt::Debug for Counted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Counted<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        T::arbitrary(u).map(Self)
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        T::size_hint(depth)
+    }
+}
+
+impl<T: Shrink> Shrink for Counted<T> {
+    fn shrink(&self) -> Vec<Self> {
+        self.0.shrink().into_iter().map(Self).collect()
+    }
+}
+
+impl<T: FromTrace> FromTrace for Counted<T> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        T::from_trace(text).map(Self)
+    }
+}
@@ -0,0 +1,147 @@
+//! Sequences of operations saved as JSON or RON.
+//!
+//! With the `serde` feature the `Op` enum of every specification derives
+//! `Serialize` and `Deserialize`, so that sequences can be written by hand,
+//! kept along with the tests and replayed directly, without going through
+//! the bytes `Arbitrary` decodes them from.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// An error saving or loading a sequence.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Ron(ron::Error),
+    /// The extension of the file is neither `json` nor `ron`.
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Json(error) => write!(f, "{error}"),
+            Self::Ron(error) => write!(f, "{error}"),
+            Self::UnknownFormat(path) => {
+                write!(f, "{} is neither a `.json` nor a `.ron` file", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<ron::Error> for Error {
+    fn from(error: ron::Error) -> Self {
+        Self::Ron(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for Error {
+    fn from(error: ron::error::SpannedError) -> Self {
+        Self::Ron(error.code)
+    }
+}
+
+/// Lays out a list with one item per line, in the same way in both formats.
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        "[]\n".to_owned()
+    } else {
+        format!("[\n    {}\n]\n", items.join(",\n    "))
+    }
+}
+
+/// Writes the operations out as JSON, one per line.
+///
+/// # Errors
+///
+/// Fails if an operation cannot be serialized.
+pub fn to_json<O: Serialize>(ops: &[O]) -> Result<String, Error> {
+    let lines = ops
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(list(&lines))
+}
+
+/// Reads operations from JSON.
+///
+/// # Errors
+///
+/// Fails if the JSON is not a sequence of operations.
+pub fn from_json<O: DeserializeOwned>(json: &str) -> Result<Vec<O>, Error> {
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Writes the operations out as RON, one per line.
+///
+/// # Errors
+///
+/// Fails if an operation cannot be serialized.
+pub fn to_ron<O: Serialize>(ops: &[O]) -> Result<String, Error> {
+    let lines = ops
+        .iter()
+        .map(ron::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(list(&lines))
+}
+
+/// Reads operations from RON.
+///
+/// # Errors
+///
+/// Fails if the RON is not a sequence of operations.
+pub fn from_ron<O: DeserializeOwned>(ron: &str) -> Result<Vec<O>, Error> {
+    Ok(ron::from_str(ron)?)
+}
+
+/// Saves the operations to a file, as JSON or RON depending on its
+/// extension.
+///
+/// # Errors
+///
+/// Fails if the extension is neither `json` nor `ron`, or if the operations
+/// cannot be serialized or written.
+pub fn save<O: Serialize>(path: impl AsRef<Path>, ops: &[O]) -> Result<(), Error> {
+    let path = path.as_ref();
+    let contents = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => to_json(ops)?,
+        Some("ron") => to_ron(ops)?,
+        _ => return Err(Error::UnknownFormat(path.to_owned())),
+    };
+    Ok(std::fs::write(path, contents)?)
+}
+
+/// Loads operations from a file, as JSON or RON depending on its extension.
+///
+/// # Errors
+///
+/// Fails if the extension is neither `json` nor `ron`, or if the file cannot
+/// be read or is not a sequence of operations.
+pub fn load<O: DeserializeOwned>(path: impl AsRef<Path>) -> Result<Vec<O>, Error> {
+    let path = path.as_ref();
+    let from = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => from_json,
+        Some("ron") => from_ron,
+        _ => return Err(Error::UnknownFormat(path.to_owned())),
+    };
+    from(&std::fs::read_to_string(path)?)
+}
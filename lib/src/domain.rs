@@ -0,0 +1,239 @@
+//! Argument types drawn from narrow domains, to be used as the keys and the
+//! values of a specification.
+//!
+//! The integers and the strings `Arbitrary` decodes are spread so thin that
+//! keys seldom collide or share a prefix. [`Small`] keys are few enough to
+//! be overwritten and removed again, [`Prefixed`] strings share prefixes the
+//! way the keys of a trie or the text of a rope should, and [`Boundary`]
+//! integers are mostly the values off-by-one errors lurk around.
+//!
+//! ```ignore
+//! arbitrary_stateful_operations! {
+//!     model = ModelTrie<Prefixed, Small<8>>,
+//!     tested = Trie<Prefixed, Small<8>>,
+//!     ...
+//! }
+//! ```
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::Deref;
+
+use crate::closures::Project;
+use crate::minimize::Shrink;
+use crate::trace::{self, FromTrace};
+
+/// The characters of the suffixes of the [`Prefixed`] strings, one of them
+/// taking two bytes in UTF-8.
+pub const ALPHABET: [char; 4] = ['a', 'b', 'c', 'é'];
+
+/// The prefixes of the [`Prefixed`] strings.
+pub const PREFIXES: [&str; 7] = ["", "a", "ab", "aba", "b", "ba", "é"];
+
+/// How many characters the suffix of a [`Prefixed`] string has at most.
+pub const MAX_SUFFIX: usize = 4;
+
+/// A value in `0..N`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "usize", into = "usize")
+)]
+pub struct Small<const N: usize>(usize);
+
+impl<const N: usize> Small<N> {
+    /// The value, if it is in `0..N`.
+    #[must_use]
+    pub fn new(value: usize) -> Option<Self> {
+        (value < N).then_some(Self(value))
+    }
+
+    #[must_use]
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl<const N: usize> TryFrom<usize> for Small<N> {
+    type Error = String;
+
+    fn try_from(value: usize) -> Result<Self, String> {
+        Self::new(value).ok_or_else(|| format!("`{value}` is not in `0..{N}`"))
+    }
+}
+
+impl<const N: usize> From<Small<N>> for usize {
+    fn from(small: Small<N>) -> Self {
+        small.0
+    }
+}
+
+// Like the value, so that the traces read the same.
+impl<const N: usize> fmt::Debug for Small<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a, const N: usize> Arbitrary<'a> for Small<N> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        const { assert!(N > 0, "`Small<0>` has no values") };
+        u.int_in_range(0..=N - 1).map(Self)
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        usize::size_hint(depth)
+    }
+}
+
+impl<const N: usize> Shrink for Small<N> {
+    fn shrink(&self) -> Vec<Self> {
+        self.0.shrink().into_iter().map(Self).collect()
+    }
+}
+
+impl<const N: usize> FromTrace for Small<N> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        Self::try_from(usize::from_trace(text)?).map_err(trace::Error::new)
+    }
+}
+
+impl<const N: usize> Project for Small<N> {
+    fn project(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+/// A string made of one of the [`PREFIXES`] followed by up to
+/// [`MAX_SUFFIX`] characters of the [`ALPHABET`].
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Prefixed(pub String);
+
+impl Deref for Prefixed {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Hashes and compares like the string, so that maps can be looked up by it.
+impl Borrow<str> for Prefixed {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+// Like the string, so that the traces read the same.
+impl fmt::Debug for Prefixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Prefixed {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut value = (*u.choose(&PREFIXES)?).to_owned();
+        for _ in 0..u.int_in_range(0..=MAX_SUFFIX)? {
+            value.push(*u.choose(&ALPHABET)?);
+        }
+        Ok(Self(value))
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (0, Some(2 + MAX_SUFFIX))
+    }
+}
+
+impl Shrink for Prefixed {
+    fn shrink(&self) -> Vec<Self> {
+        self.0.shrink().into_iter().map(Self).collect()
+    }
+}
+
+impl FromTrace for Prefixed {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        String::from_trace(text).map(Self)
+    }
+}
+
+/// An integer that is mostly zero, one, the minimum, the maximum or next to
+/// a power of two, and otherwise any.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Boundary<T>(pub T);
+
+impl<T> Deref for Boundary<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// Like the value, so that the traces read the same.
+impl<T: fmt::Debug> fmt::Debug for Boundary<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+macro_rules! impl_arbitrary_boundary {
+    ($($ty:ty),*) => {
+        $(
+            impl<'a> Arbitrary<'a> for Boundary<$ty> {
+                fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+                    let value = match u.int_in_range(0..=2_u8)? {
+                        0 => *u.choose(&[
+                            0,
+                            1,
+                            <$ty>::MIN,
+                            <$ty>::MAX,
+                            <$ty>::MIN.wrapping_add(1),
+                            <$ty>::MAX.wrapping_sub(1),
+                            (0 as $ty).wrapping_sub(1),
+                        ])?,
+                        1 => {
+                            let power = (1 as $ty).wrapping_shl(u.int_in_range(0..=<$ty>::BITS - 1)?);
+                            match u.int_in_range(0..=2_u8)? {
+                                0 => power,
+                                1 => power.wrapping_sub(1),
+                                _ => power.wrapping_add(1),
+                            }
+                        }
+                        _ => u.arbitrary()?,
+                    };
+                    Ok(Self(value))
+                }
+
+                fn size_hint(depth: usize) -> (usize, Option<usize>) {
+                    arbitrary::size_hint::and(u8::size_hint(depth), <$ty>::size_hint(depth))
+                }
+            }
+        )*
+    };
+}
+
+impl_arbitrary_boundary!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+impl<T: Shrink> Shrink for Boundary<T> {
+    fn shrink(&self) -> Vec<Self> {
+        self.0.shrink().into_iter().map(Self).collect()
+    }
+}
+
+impl<T: FromTrace> FromTrace for Boundary<T> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        T::from_trace(text).map(Self)
+    }
+}
+
+impl<T: Project> Project for Boundary<T> {
+    fn project(&self) -> u64 {
+        self.0.project()
+    }
+}
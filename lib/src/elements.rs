@@ -0,0 +1,656 @@
+//! Elements telling how the tested instance treats them.
+//!
+//! Every [`Tracked`] element is recorded in a registry of the thread while
+//! alive, and its clones are told apart from it. Once a sequence is over and
+//! both the model and the tested instance are dropped, the runners check
+//! that every element created during the sequence was dropped, and none
+//! more than once, which catches the leaks and the double drops of the
+//! unsafe code of a structure:
+//!
+//! ```ignore
+//! arbitrary_stateful_operations! {
+//!     model = ModelHashMap<Tracked<u16>, Tracked<u16>>,
+//!     tested = HashMap<Tracked<u16>, Tracked<u16>>,
+//!     ...
+//! }
+//! ```
+//!
+//! The elements have to be dropped on the thread that created them.
+//!
+//! A [`Panicky`] element panics on a call of its `Clone`, `PartialEq`,
+//! `PartialOrd`, `Ord` or `Hash` methods chosen by the input, which checks
+//! that a structure stays sound when they unwind. The model and the tested
+//! instance can no longer be expected to agree once that happened, so the
+//! generated code catches the panic on both sides and the rest of the
+//! sequence only runs on the tested instance, checking the `consistency`
+//! block of the specification after every operation instead of comparing
+//! their results. Wrapping [`Tracked`] elements, as in
+//! `Panicky<Tracked<u16>>`, also checks that nothing leaked or was dropped
+//! twice on the way.
+//!
+//! An [`Erratic`] element orders itself inconsistently, as the input
+//! chooses. That is a logic error rather than undefined behaviour, so a
+//! structure may return anything but has to stay sound, and the rest of the
+//! sequence is checked the same way once an element lied.
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+use crate::failure::{self, Failure};
+use crate::minimize::Shrink;
+use crate::trace::{self, FromTrace};
+
+struct Registry {
+    next_id: u64,
+    // The first id of the sequence being executed on the thread.
+    start_id: u64,
+    live: BTreeSet<u64>,
+    dropped_twice: usize,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = const {
+        RefCell::new(Registry {
+            next_id: 0,
+            start_id: 0,
+            live: BTreeSet::new(),
+            dropped_twice: 0,
+        })
+    };
+    // Whether an element panicked or lied during the operation being
+    // executed.
+    static INJECTED: Cell<bool> = const { Cell::new(false) };
+    // Whether an element panicked earlier in the sequence.
+    static DIVERGED: Cell<bool> = const { Cell::new(false) };
+    // The side being called by the generated code, if any.
+    static SIDE: Cell<Option<Side>> = const { Cell::new(None) };
+}
+
+/// The side of a comparison the generated code is calling. [`Panicky`] and
+/// [`Erratic`] elements count their calls on each side apart, and none
+/// outside of them.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Model,
+    Tested,
+}
+
+/// Restores the side called before when dropped.
+#[doc(hidden)]
+pub struct Entered {
+    previous: Option<Side>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        SIDE.with(|side| side.set(self.previous));
+    }
+}
+
+/// Calls the given side until the guard is dropped. Called by the generated
+/// code.
+#[doc(hidden)]
+#[must_use]
+pub fn enter(side: Side) -> Entered {
+    Entered {
+        previous: SIDE.with(|current| current.replace(Some(side))),
+    }
+}
+
+/// Whether the generated code is calling the model or the tested instance
+/// on the thread, which catches and compares their panics.
+pub(crate) fn comparing() -> bool {
+    SIDE.with(|side| side.get().is_some())
+}
+
+/// How many elements created during the sequence being executed on the
+/// thread are alive, and how many drops were of elements dropped already.
+#[must_use]
+pub fn counts() -> (usize, usize) {
+    REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        (registry.live.range(registry.start_id..).count(), registry.dropped_twice)
+    })
+}
+
+/// Starts another sequence: the elements alive already, such as those of the
+/// operations decoded beforehand, may outlive it. Called when the numbering
+/// of the operations is reset.
+pub(crate) fn start_sequence() {
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.start_id = registry.next_id;
+        registry.dropped_twice = 0;
+    });
+    INJECTED.with(|injected| injected.set(false));
+    DIVERGED.with(|diverged| diverged.set(false));
+}
+
+/// Whether a [`Panicky`] element panicked or an [`Erratic`] one lied during
+/// the sequence being executed on the thread, after which the model is no
+/// longer compared.
+#[must_use]
+pub fn diverged() -> bool {
+    DIVERGED.with(Cell::get)
+}
+
+/// Whether an element panicked or lied since the last call. Called by the
+/// generated code.
+#[doc(hidden)]
+#[must_use]
+pub fn take_injected() -> bool {
+    INJECTED.with(|injected| injected.replace(false))
+}
+
+fn inject() {
+    INJECTED.with(|injected| injected.set(true));
+}
+
+/// The payload unwinding out of an operation once an element panicked or
+/// lied. Caught by the generated code.
+#[doc(hidden)]
+pub struct Diverged;
+
+/// Stops comparing the model for the rest of the sequence and unwinds out
+/// of the operation. Called by the generated code.
+#[doc(hidden)]
+pub fn diverge() -> ! {
+    DIVERGED.with(|diverged| diverged.set(true));
+    // Not a panic: the hook has nothing to report.
+    std::panic::resume_unwind(Box::new(Diverged))
+}
+
+/// Fails if an element created during the sequence is still alive, or if
+/// one was dropped more than once. To be called once the model and the
+/// tested instance are dropped.
+pub fn check_drops() {
+    let (leaked, dropped_twice) = counts();
+    if leaked > 0 || dropped_twice > 0 {
+        // Reported once.
+        start_sequence();
+        failure::fail(Failure::UnbalancedDrops { leaked, dropped_twice });
+    }
+}
+
+/// An element recorded in the registry of the thread while alive. Compares,
+/// hashes and prints like its value.
+pub struct Tracked<T> {
+    value: T,
+    id: u64,
+}
+
+impl<T> Tracked<T> {
+    #[must_use]
+    pub fn new(value: T) -> Self {
+        let id = REGISTRY.with(|registry| {
+            let mut registry = registry.borrow_mut();
+            let id = registry.next_id;
+            registry.next_id += 1;
+            registry.live.insert(id);
+            id
+        });
+        Self { value, id }
+    }
+
+    /// The value.
+    #[must_use]
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> Drop for Tracked<T> {
+    fn drop(&mut self) {
+        // The registry may be gone if the thread is exiting.
+        let _ = REGISTRY.try_with(|registry| {
+            let mut registry = registry.borrow_mut();
+            if !registry.live.remove(&self.id) {
+                registry.dropped_twice += 1;
+            }
+        });
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+// Another element, with an id of its own.
+impl<T: Clone> Clone for Tracked<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone())
+    }
+}
+
+impl<T: Default> Default for Tracked<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: PartialEq> PartialEq for Tracked<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Tracked<T> {}
+
+impl<T: PartialOrd> PartialOrd for Tracked<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<T: Ord> Ord for Tracked<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: Hash> Hash for Tracked<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
+// Like the value, so that the traces read the same.
+impl<T: fmt::Debug> fmt::Debug for Tracked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Tracked<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        T::arbitrary(u).map(Self::new)
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        T::size_hint(depth)
+    }
+}
+
+impl<T: Shrink> Shrink for Tracked<T> {
+    fn shrink(&self) -> Vec<Self> {
+        self.value.shrink().into_iter().map(Self::new).collect()
+    }
+}
+
+impl<T: FromTrace> FromTrace for Tracked<T> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        T::from_trace(text).map(Self::new)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for Tracked<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Tracked<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// An element that panics on a call of its `Clone`, `PartialEq`,
+/// `PartialOrd`, `Ord` or `Hash` methods, the one of the given index counting
+/// from zero, if any. Compares, hashes and prints like its value otherwise.
+///
+/// The calls are only counted while the generated code calls the model or
+/// the tested instance, separately for each, so that an argument passed by
+/// reference to both gets the same count on either side. A clone is a new
+/// element whose first call is its own creation, so that cloning the
+/// operations, as the runners do, never counts towards the original.
+pub struct Panicky<T> {
+    value: T,
+    panic_on_call: Option<u8>,
+    // The calls on the model and on the tested instance.
+    calls: [Cell<u32>; 2],
+}
+
+impl<T> Panicky<T> {
+    #[must_use]
+    pub fn new(value: T, panic_on_call: Option<u8>) -> Self {
+        Self {
+            value,
+            panic_on_call,
+            calls: [Cell::new(0), Cell::new(0)],
+        }
+    }
+
+    /// The value.
+    #[must_use]
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    fn count_call(&self) {
+        let Some(side) = SIDE.with(Cell::get) else {
+            return;
+        };
+        let calls = &self.calls[side as usize];
+        let call = calls.get();
+        calls.set(call + 1);
+        if self.panic_on_call.map(u32::from) == Some(call) {
+            inject();
+            crate::hook::deliberate();
+            #[allow(clippy::panic)]
+            {
+                panic!("the element panicked on call {call}");
+            }
+        }
+    }
+}
+
+impl<T> Deref for Panicky<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> Clone for Panicky<T> {
+    fn clone(&self) -> Self {
+        let clone = Self::new(self.value.clone(), self.panic_on_call);
+        clone.count_call();
+        clone
+    }
+}
+
+impl<T: PartialEq> PartialEq for Panicky<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.count_call();
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Panicky<T> {}
+
+impl<T: PartialOrd> PartialOrd for Panicky<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.count_call();
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl<T: Ord> Ord for Panicky<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.count_call();
+        self.value.cmp(&other.value)
+    }
+}
+
+impl<T: Hash> Hash for Panicky<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.count_call();
+        self.value.hash(state);
+    }
+}
+
+// Like the value unless it panics, so that the traces read the same.
+impl<T: fmt::Debug> fmt::Debug for Panicky<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.panic_on_call {
+            Some(call) => write!(f, "Panicky::new({:?}, Some({call}))", self.value),
+            None => self.value.fmt(f),
+        }
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Panicky<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::new(T::arbitrary(u)?, u.arbitrary()?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        arbitrary::size_hint::and(T::size_hint(depth), <Option<u8>>::size_hint(depth))
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for Panicky<T> {
+    fn shrink(&self) -> Vec<Self> {
+        let mut shrunk = vec![];
+        if self.panic_on_call.is_some() {
+            shrunk.push(Self::new(self.value.clone(), None));
+        }
+        shrunk.extend(self.value.shrink().into_iter().map(|value| Self::new(value, self.panic_on_call)));
+        shrunk
+    }
+}
+
+impl<T: FromTrace> FromTrace for Panicky<T> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        let Some(args) = trace::enclosed(text, "Panicky::new(", ")") else {
+            return T::from_trace(text).map(|value| Self::new(value, None));
+        };
+        match trace::split(args, ',').as_slice() {
+            [value, panic_on_call] => Ok(Self::new(T::from_trace(value)?, FromTrace::from_trace(panic_on_call)?)),
+            _ => Err(trace::Error::new(format!("`{}` is not a `Panicky`", text.trim()))),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for Panicky<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (&self.value, self.panic_on_call).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Panicky<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <(T, Option<u8>)>::deserialize(deserializer).map(|(value, panic_on_call)| Self::new(value, panic_on_call))
+    }
+}
+
+/// How an [`Erratic`] element lies about its order. The calls of its
+/// `PartialEq`, `PartialOrd` and `Ord` methods are counted from one.
+#[derive(Arbitrary, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Lie {
+    /// Orders itself like its value.
+    Never,
+    /// Reverses the order on every call of the given multiple.
+    Reverse { every: u8 },
+    /// Claims to be equal on every call of the given multiple.
+    Equal { every: u8 },
+    /// Answers at random, as the seed and the number of the call decide.
+    Random { seed: u64 },
+}
+
+impl Lie {
+    fn answer(self, call: u32, truth: Ordering) -> Ordering {
+        let every = |every: u8| call.is_multiple_of(u32::from(every.max(1)));
+        match self {
+            Self::Reverse { every: n } if every(n) => truth.reverse(),
+            Self::Equal { every: n } if every(n) => Ordering::Equal,
+            Self::Never | Self::Reverse { .. } | Self::Equal { .. } => truth,
+            Self::Random { seed } => match crate::hashing::mix(seed ^ u64::from(call)) % 3 {
+                0 => Ordering::Less,
+                1 => Ordering::Equal,
+                _ => Ordering::Greater,
+            },
+        }
+    }
+}
+
+impl FromTrace for Lie {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        let text = text.trim();
+        let field = |variant: &str, field: &str| {
+            trace::enclosed(text, &format!("Lie::{variant} {{ {field}: "), " }")
+        };
+        if text == "Lie::Never" {
+            Ok(Self::Never)
+        } else if let Some(every) = field("Reverse", "every") {
+            Ok(Self::Reverse { every: FromTrace::from_trace(every)? })
+        } else if let Some(every) = field("Equal", "every") {
+            Ok(Self::Equal { every: FromTrace::from_trace(every)? })
+        } else if let Some(seed) = field("Random", "seed") {
+            Ok(Self::Random { seed: FromTrace::from_trace(seed)? })
+        } else {
+            Err(trace::Error::new(format!("`{text}` is not a `Lie`")))
+        }
+    }
+}
+
+/// An element that orders itself inconsistently, as its [`Lie`] decides.
+/// Its equality follows its order, and it hashes like its value whatever it
+/// claims to be equal to. Its clones count their calls from one again.
+///
+/// Like those of a [`Panicky`] element, the calls are counted separately on
+/// the model and on the tested instance, so that both get the same answers.
+/// Outside of them, as when the generated code compares the values they
+/// returned, it orders itself like its value.
+pub struct Erratic<T> {
+    value: T,
+    lie: Lie,
+    // The calls on the model and on the tested instance.
+    calls: [Cell<u32>; 2],
+}
+
+impl<T> Erratic<T> {
+    #[must_use]
+    pub fn new(value: T, lie: Lie) -> Self {
+        Self {
+            value,
+            lie,
+            calls: [Cell::new(0), Cell::new(0)],
+        }
+    }
+
+    /// The value.
+    #[must_use]
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    fn answer(&self, truth: Ordering) -> Ordering {
+        let Some(side) = SIDE.with(Cell::get) else {
+            return truth;
+        };
+        let calls = &self.calls[side as usize];
+        let call = calls.get().wrapping_add(1);
+        calls.set(call);
+        let answer = self.lie.answer(call, truth);
+        if answer != truth {
+            inject();
+        }
+        answer
+    }
+}
+
+impl<T> Deref for Erratic<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Clone> Clone for Erratic<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.value.clone(), self.lie)
+    }
+}
+
+impl<T: Ord> PartialEq for Erratic<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.answer(self.value.cmp(&other.value)) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Erratic<T> {}
+
+impl<T: Ord> PartialOrd for Erratic<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> Ord for Erratic<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.answer(self.value.cmp(&other.value))
+    }
+}
+
+impl<T: Hash> Hash for Erratic<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+    }
+}
+
+// Like the value unless it lies, so that the traces read the same.
+impl<T: fmt::Debug> fmt::Debug for Erratic<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lie {
+            Lie::Never => self.value.fmt(f),
+            lie => write!(f, "Erratic::new({:?}, Lie::{lie:?})", self.value),
+        }
+    }
+}
+
+impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Erratic<T> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::new(T::arbitrary(u)?, u.arbitrary()?))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        arbitrary::size_hint::and(T::size_hint(depth), Lie::size_hint(depth))
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for Erratic<T> {
+    fn shrink(&self) -> Vec<Self> {
+        let mut shrunk = vec![];
+        if self.lie != Lie::Never {
+            shrunk.push(Self::new(self.value.clone(), Lie::Never));
+        }
+        shrunk.extend(self.value.shrink().into_iter().map(|value| Self::new(value, self.lie)));
+        shrunk
+    }
+}
+
+impl<T: FromTrace> FromTrace for Erratic<T> {
+    fn from_trace(text: &str) -> Result<Self, trace::Error> {
+        let Some(args) = trace::enclosed(text, "Erratic::new(", ")") else {
+            return T::from_trace(text).map(|value| Self::new(value, Lie::Never));
+        };
+        match trace::split(args, ',').as_slice() {
+            [value, lie] => Ok(Self::new(T::from_trace(value)?, Lie::from_trace(lie)?)),
+            _ => Err(trace::Error::new(format!("`{}` is not an `Erratic`", text.trim()))),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for Erratic<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (&self.value, self.lie).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Erratic<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <(T, Lie)>::deserialize(deserializer).map(|(value, lie)| Self::new(value, lie))
+    }
+}
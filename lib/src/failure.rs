@@ -0,0 +1,195 @@
+//! The ways the tested instance can fail to behave like the model.
+//!
+//! A failure is raised as the payload of a panic, see [`fail`], so that
+//! tooling catching it can tell the failures apart by downcasting the
+//! payload to [`Failure`] instead of parsing a message.
+
+use std::cell::Cell;
+use std::fmt;
+
+/// A difference between the model and the tested instance.
+///
+/// The operations are numbered from zero, since the last call of
+/// [`reset_op_index`] on the thread.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    /// Both returned, but different values.
+    ReturnMismatch {
+        op_index: usize,
+        op_name: &'static str,
+        model: String,
+        tested: String,
+    },
+    /// Exactly one of them panicked.
+    PanicMismatch {
+        op_index: usize,
+        op_name: &'static str,
+        model_panicked: bool,
+        tested_panicked: bool,
+    },
+    /// A statement of the `post` block panicked after the operation.
+    InvariantViolation {
+        op_index: usize,
+        op_name: &'static str,
+        message: String,
+    },
+    /// No sequential order of the calls made by several threads explains
+    /// their outcomes. The history is the shortest failing prefix.
+    NotLinearizable { history: String },
+    /// Once the sequence was over and both were dropped, elements created
+    /// during it were still alive, or some were dropped more than once. See
+    /// [`crate::elements`].
+    UnbalancedDrops { leaked: usize, dropped_twice: usize },
+    /// Raised with a message of its own, by the deprecated
+    /// [`rutenspitz::panic!`](crate::panic) macro.
+    Other { message: String },
+}
+
+impl Failure {
+    /// The index of the operation that failed, if the failure is down to a
+    /// single one.
+    #[must_use]
+    pub fn op_index(&self) -> Option<usize> {
+        match *self {
+            Self::ReturnMismatch { op_index, .. }
+            | Self::PanicMismatch { op_index, .. }
+            | Self::InvariantViolation { op_index, .. } => Some(op_index),
+            Self::NotLinearizable { .. } | Self::UnbalancedDrops { .. } | Self::Other { .. } => None,
+        }
+    }
+
+    /// The name of the operation that failed, if the failure is down to a
+    /// single one.
+    #[must_use]
+    pub fn op_name(&self) -> Option<&'static str> {
+        match *self {
+            Self::ReturnMismatch { op_name, .. }
+            | Self::PanicMismatch { op_name, .. }
+            | Self::InvariantViolation { op_name, .. } => Some(op_name),
+            Self::NotLinearizable { .. } | Self::UnbalancedDrops { .. } | Self::Other { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(op_index), Some(op_name)) = (self.op_index(), self.op_name()) {
+            write!(f, "Operation #{op_index} `{op_name}`: ")?;
+        }
+        match self {
+            Self::ReturnMismatch { model, tested, .. } => {
+                write!(f, "The return values aren't equal: `{model}` != `{tested}`")
+            }
+            Self::PanicMismatch {
+                model_panicked: true,
+                ..
+            } => write!(f, "Implementation did not panic while the model did"),
+            Self::PanicMismatch { .. } => {
+                write!(f, "Implementation panicked while the model did not")
+            }
+            Self::InvariantViolation { message, .. } => {
+                write!(f, "An invariant doesn't hold: {message}")
+            }
+            Self::NotLinearizable { history } => {
+                write!(f, "The history is not linearizable:\n{history}")
+            }
+            Self::UnbalancedDrops { leaked, dropped_twice } => write!(
+                f,
+                "The elements were not dropped exactly once: {leaked} leaked, {dropped_twice} dropped again"
+            ),
+            Self::Other { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Panics with the failure as the payload.
+///
+/// Outside of `fuzzing_debug` builds the panic hook of the crate aborts on
+/// such panics; otherwise the failure is printed first, as the default hook
+/// cannot print the payload. If a sequence is being recorded on the thread,
+/// it is written out as a regression test beforehand, see
+/// [`crate::regression`].
+pub fn fail(failure: Failure) -> ! {
+    if !QUIET.with(Cell::get) {
+        #[cfg(fuzzing_debug)]
+        eprintln!("{failure}");
+
+        match crate::regression::write(&failure) {
+            Some(Ok(path)) => eprintln!("A regression test was written to {}", path.display()),
+            Some(Err(error)) => eprintln!("The regression test could not be written: {error}"),
+            None => {}
+        }
+    }
+
+    #[allow(clippy::panic)]
+    std::panic::panic_any(failure)
+}
+
+/// Renders the payload of a panic that was not raised with [`fail`].
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+thread_local! {
+    static OP_INDEX: Cell<usize> = const { Cell::new(0) };
+    static QUIET: Cell<bool> = const { Cell::new(false) };
+}
+
+// Restores whether the thread was quiet when dropped, even on unwinding.
+struct Quiet(bool);
+
+impl Drop for Quiet {
+    fn drop(&mut self) {
+        QUIET.with(|quiet| quiet.set(self.0));
+    }
+}
+
+/// Runs `f` without printing the failures it raises on the thread or
+/// writing regression tests for them.
+pub(crate) fn quietly<R>(f: impl FnOnce() -> R) -> R {
+    let _quiet = Quiet(QUIET.with(|quiet| quiet.replace(true)));
+    f()
+}
+
+/// Runs `f` quietly, with the panics silenced too. The panic hook is
+/// restored afterwards, even if `f` unwinds.
+///
+/// The panic hook is global to the process, so the panics of the other
+/// threads are silenced as well while `f` runs.
+pub(crate) fn silently<R>(f: impl FnOnce() -> R) -> R {
+    // The hook is set here if not yet, rather than by the first operation.
+    #[cfg(not(fuzzing_debug))]
+    lazy_static::initialize(&crate::NON_DEBUG_PANIC_HOOK);
+
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    // The hook cannot be set while the thread is panicking, so an unwinding
+    // `f` is caught and resumed once it is restored.
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| quietly(f)));
+    std::panic::set_hook(hook);
+    result.unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+}
+
+/// Starts numbering the operations executed on the thread from zero again.
+/// To be called at the start of every sequence.
+pub fn reset_op_index() {
+    #[cfg(feature = "stats")]
+    crate::stats::start_sequence();
+    crate::elements::start_sequence();
+    OP_INDEX.with(|index| index.set(0));
+}
+
+/// Returns the index of the next operation executed on the thread and
+/// advances it.
+#[must_use]
+pub fn next_op_index() -> usize {
+    OP_INDEX.with(|index| index.replace(index.get() + 1))
+}
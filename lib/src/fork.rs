@@ -0,0 +1,138 @@
+//! Forking a model and a tested instance into further live pairs.
+//!
+//! A [`Step`] either clones one of the pairs into a new one or executes an
+//! operation on one of them, so that the operations on the original and on
+//! its clones interleave. Mutations leaking through a structure shared
+//! between the clones, as with copy-on-write or `Rc`, then show up as
+//! differences from the model, whose clones are independent.
+//!
+//! A specification with `fork = true` defines `op::Step` as the steps of
+//! its operations.
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::fmt;
+
+use crate::Operation;
+
+/// The greatest number of live pairs. Forking once there are as many
+/// replaces the last one.
+pub const MAX_PAIRS: usize = 4;
+
+/// A step of a sequence: a fork or an operation on one of the pairs.
+///
+/// The pairs are referred to by indices taken modulo the number of live
+/// pairs and the pair a fork creates depends on their number too, see
+/// [`Pairs::resolve`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Step<O> {
+    Fork { from: u8, to: u8 },
+    Op { pair: u8, op: O },
+}
+
+impl<'a, O: Arbitrary<'a>> Arbitrary<'a> for Step<O> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        // Forks are much rarer than operations on the pairs.
+        if u8::arbitrary(u)? < 16 {
+            Ok(Self::Fork {
+                from: u8::arbitrary(u)?,
+                to: 0,
+            })
+        } else {
+            Ok(Self::Op {
+                pair: u8::arbitrary(u)?,
+                op: O::arbitrary(u)?,
+            })
+        }
+    }
+}
+
+/// Writes out a step whose indices have been resolved. The first pair is
+/// `v` like in the trace of a single pair and the others are `v1`, `v2` and
+/// so on.
+impl<O: fmt::Display> fmt::Display for Step<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Name(u8);
+
+        impl fmt::Display for Name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match self.0 {
+                    0 => write!(f, "v"),
+                    index => write!(f, "v{index}"),
+                }
+            }
+        }
+
+        match *self {
+            Self::Fork { from, to } => {
+                write!(f, "let mut {} = {}.clone();", Name(to), Name(from))
+            }
+            Self::Op { pair: 0, ref op } => write!(f, "{op}"),
+            Self::Op { pair, ref op } => {
+                let op = op.to_string().replace('\n', "\n    ");
+                write!(f, "{{\n    let v = &mut {};\n    {op}\n}}", Name(pair))
+            }
+        }
+    }
+}
+
+impl<O: fmt::Display> Step<O> {
+    #[cfg_attr(not(fuzzing_debug), allow(unused_variables))]
+    pub fn append_to_trace(&self, trace: &mut String) {
+        #[cfg(fuzzing_debug)]
+        trace.push_str(&format!("{self}\n"));
+    }
+}
+
+/// The live pairs of a model and a tested instance, starting with one.
+pub struct Pairs<M, T> {
+    pairs: Vec<(M, T)>,
+}
+
+impl<M: Clone, T: Clone> Pairs<M, T> {
+    pub fn new(model: M, tested: T) -> Self {
+        Self {
+            pairs: vec![(model, tested)],
+        }
+    }
+
+    /// Reduces the indices of the step modulo the number of live pairs and
+    /// sets the pair a fork creates: the next one or, if there are already
+    /// [`MAX_PAIRS`] of them, the last one.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn resolve<O>(&self, step: Step<O>) -> Step<O> {
+        let resolve = |index: u8| (usize::from(index) % self.pairs.len()) as u8;
+        match step {
+            Step::Fork { from, .. } => Step::Fork {
+                from: resolve(from),
+                to: self.pairs.len().min(MAX_PAIRS - 1) as u8,
+            },
+            Step::Op { pair, op } => Step::Op {
+                pair: resolve(pair),
+                op,
+            },
+        }
+    }
+
+    /// Executes the step, comparing the results of the operation on the
+    /// model and on the tested instance of the pair. The step is resolved
+    /// first, which leaves a step resolved already unchanged.
+    pub fn execute_and_compare<O: Operation<M, T>>(&mut self, step: Step<O>) {
+        match self.resolve(step) {
+            Step::Fork { from, to } => {
+                let (model, tested) = &self.pairs[usize::from(from)];
+                let pair = (model.clone(), tested.clone());
+                if usize::from(to) < self.pairs.len() {
+                    self.pairs[usize::from(to)] = pair;
+                } else {
+                    self.pairs.push(pair);
+                }
+            }
+            Step::Op { pair, op } => {
+                let (model, tested) = &mut self.pairs[usize::from(pair)];
+                op.execute_and_compare(model, tested);
+            }
+        }
+    }
+}
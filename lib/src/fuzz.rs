@@ -0,0 +1,154 @@
+//! Fuzzing targets independent of the fuzzing engine.
+//!
+//! [`fuzz_target!`](crate::fuzz_target) defines the entry point of a target
+//! for the engine selected by one of the `honggfuzz`, `libfuzzer-sys` and
+//! `afl` features, or one running the inputs in the files given on the
+//! command line without any. Before every input it resets the numbering of
+//! the operations and the ring buffer of the most recent ones, and the first
+//! time it installs the panic hook, unless one was installed already. After
+//! every input it checks the drops of the [`crate::elements`]. The
+//! hook of libFuzzer, and that of honggfuzz outside of debug builds, abort
+//! on every panic, including those the generated code catches, so the
+//! previous hook is only chained in `fuzzing_debug` builds and never under
+//! libFuzzer.
+
+use std::io::Read as _;
+use std::sync::Once;
+
+static START: Once = Once::new();
+
+/// Whether this is a `fuzzing_debug` build.
+#[doc(hidden)]
+pub const DEBUG: bool = cfg!(fuzzing_debug);
+
+/// Prepares for running the sequence decoded from the input. The first
+/// time, runs `init` and installs the panic hook. Called by `fuzz_target!`.
+#[doc(hidden)]
+pub fn start(data: &[u8], chain_previous: bool, init: impl FnOnce()) {
+    START.call_once(|| {
+        init();
+        if !crate::hook::installed() {
+            crate::hook().chain_previous(chain_previous).install();
+        }
+    });
+    crate::failure::reset_op_index();
+    crate::recent::start(data);
+}
+
+/// Checks the drops of the elements once the sequence is over, see
+/// [`crate::elements`]. Called by `fuzz_target!`.
+#[doc(hidden)]
+pub fn finish() {
+    crate::elements::check_drops();
+}
+
+/// Runs the inputs in the files given on the command line, or the standard
+/// input if there are none. Used by `fuzz_target!` without an engine.
+#[doc(hidden)]
+pub fn run_files(mut run: impl FnMut(&[u8])) {
+    let paths: Vec<_> = std::env::args_os().skip(1).collect();
+    if paths.is_empty() {
+        let mut data = vec![];
+        match std::io::stdin().read_to_end(&mut data) {
+            Ok(_) => run(&data),
+            Err(error) => eprintln!("The standard input could not be read: {error}"),
+        }
+    }
+    for path in paths {
+        match std::fs::read(&path) {
+            Ok(data) => run(&data),
+            Err(error) => {
+                eprintln!("{} could not be read: {error}", std::path::Path::new(&path).display());
+            }
+        }
+    }
+}
+
+/// Defines the entry point of a fuzzing target running the body on every
+/// input, for the engine selected by the features of `rutenspitz`. The
+/// optional `init` expression runs once, before the panic hook is
+/// installed, so that a hook it installs is chained in debug builds.
+///
+/// ```ignore
+/// rutenspitz::fuzz_target!(init: better_panic::install(), |data| fuzz_cycle(data));
+/// ```
+///
+/// The value of the body, such as an `arbitrary::Result`, is ignored. With
+/// `libfuzzer-sys`, the target has to be `#![no_main]` and to depend on
+/// `libfuzzer-sys` itself, which its macro refers to by name.
+#[macro_export]
+macro_rules! fuzz_target {
+    (init: $init:expr, |$data:ident $(: &[u8])?| $body:expr) => {
+        $crate::__fuzz_main!($init, |$data| $body);
+    };
+    (|$data:ident $(: &[u8])?| $body:expr) => {
+        $crate::__fuzz_main!((), |$data| $body);
+    };
+}
+
+#[cfg(any(
+    all(feature = "honggfuzz", feature = "libfuzzer-sys"),
+    all(feature = "honggfuzz", feature = "afl"),
+    all(feature = "libfuzzer-sys", feature = "afl"),
+))]
+compile_error!("only one of the `honggfuzz`, `libfuzzer-sys` and `afl` features can be enabled");
+
+#[cfg(feature = "honggfuzz")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fuzz_main {
+    ($init:expr, |$data:ident| $body:expr) => {
+        fn main() {
+            loop {
+                $crate::honggfuzz::fuzz!(|$data: &[u8]| {
+                    $crate::fuzz::start($data, $crate::fuzz::DEBUG, || $init);
+                    let _ = $body;
+                    $crate::fuzz::finish();
+                });
+            }
+        }
+    };
+}
+
+#[cfg(feature = "libfuzzer-sys")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fuzz_main {
+    ($init:expr, |$data:ident| $body:expr) => {
+        $crate::libfuzzer_sys::fuzz_target!(|$data: &[u8]| {
+            $crate::fuzz::start($data, false, || $init);
+            let _ = $body;
+            $crate::fuzz::finish();
+        });
+    };
+}
+
+#[cfg(feature = "afl")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fuzz_main {
+    ($init:expr, |$data:ident| $body:expr) => {
+        fn main() {
+            $crate::afl::fuzz_nohook!(|$data: &[u8]| {
+                $crate::fuzz::start($data, $crate::fuzz::DEBUG, || $init);
+                let _ = $body;
+                $crate::fuzz::finish();
+            });
+        }
+    };
+}
+
+#[cfg(not(any(feature = "honggfuzz", feature = "libfuzzer-sys", feature = "afl")))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fuzz_main {
+    ($init:expr, |$data:ident| $body:expr) => {
+        fn main() {
+            $crate::fuzz::run_files(|$data: &[u8]| {
+                $crate::fuzz::start($data, $crate::fuzz::DEBUG, || $init);
+                let _ = $body;
+                $crate::fuzz::finish();
+            });
+        }
+    };
+}
@@ -0,0 +1,152 @@
+//! Hashers making the hash tables take their collision paths.
+//!
+//! A [`FuzzBuildHasher`] is decoded from the input along with the tested
+//! instance, and the input also picks how it hashes: with a seeded hash
+//! like a real one, the same hash for every key, a hash whose high bits are
+//! all zero, or hashes taken from a table chosen by the input.
+//!
+//! ```ignore
+//! let hasher: FuzzBuildHasher = ring.arbitrary()?;
+//! let tested = HashMap::with_hasher(hasher);
+//! ```
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+/// How many hashes a table chosen by the input has at most.
+pub const MAX_CHOSEN: usize = 16;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Mixes the bits of a value, like the finalizer of `SplitMix64`.
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Mode {
+    Seeded(u64),
+    Constant(u64),
+    LowBits { bits: u32, seed: u64 },
+    Chosen(Arc<[u64]>),
+}
+
+/// Builds the hashers of a hash table, as chosen by the input.
+///
+/// It prints as the Rust expression building it, for the regression tests.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuzzBuildHasher {
+    mode: Mode,
+}
+
+impl FuzzBuildHasher {
+    /// Hashes like a real hasher, seeded.
+    #[must_use]
+    pub fn seeded(seed: u64) -> Self {
+        Self { mode: Mode::Seeded(seed) }
+    }
+
+    /// Hashes every key to the same value, so that they all collide.
+    #[must_use]
+    pub fn constant(hash: u64) -> Self {
+        Self {
+            mode: Mode::Constant(hash),
+        }
+    }
+
+    /// Hashes like [`FuzzBuildHasher::seeded`], keeping only the given
+    /// number of the low bits, up to 64.
+    #[must_use]
+    pub fn low_bits(bits: u32, seed: u64) -> Self {
+        Self {
+            mode: Mode::LowBits { bits: bits.min(64), seed },
+        }
+    }
+
+    /// Hashes every key to one of the given hashes, picked by the bytes of
+    /// the key, or to zero if there are none.
+    #[must_use]
+    pub fn chosen(hashes: impl Into<Arc<[u64]>>) -> Self {
+        Self {
+            mode: Mode::Chosen(hashes.into()),
+        }
+    }
+}
+
+impl Default for FuzzBuildHasher {
+    fn default() -> Self {
+        Self::seeded(0)
+    }
+}
+
+impl fmt::Display for FuzzBuildHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Seeded(seed) => write!(f, "FuzzBuildHasher::seeded({seed})"),
+            Mode::Constant(hash) => write!(f, "FuzzBuildHasher::constant({hash})"),
+            Mode::LowBits { bits, seed } => write!(f, "FuzzBuildHasher::low_bits({bits}, {seed})"),
+            Mode::Chosen(ref hashes) => write!(f, "FuzzBuildHasher::chosen(vec!{hashes:?})"),
+        }
+    }
+}
+
+impl<'a> Arbitrary<'a> for FuzzBuildHasher {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(match u.int_in_range(0..=3_u8)? {
+            0 => Self::seeded(u.arbitrary()?),
+            1 => Self::constant(u.arbitrary()?),
+            2 => Self::low_bits(u.int_in_range(0..=16)?, u.arbitrary()?),
+            _ => {
+                let len = u.int_in_range(1..=MAX_CHOSEN)?;
+                let hashes = (0..len).map(|_| u.arbitrary()).collect::<arbitrary::Result<Vec<u64>>>()?;
+                Self::chosen(hashes)
+            }
+        })
+    }
+}
+
+impl BuildHasher for FuzzBuildHasher {
+    type Hasher = FuzzHasher;
+
+    fn build_hasher(&self) -> FuzzHasher {
+        FuzzHasher {
+            mode: self.mode.clone(),
+            state: FNV_OFFSET,
+        }
+    }
+}
+
+/// A hasher built by a [`FuzzBuildHasher`].
+#[derive(Clone, Debug)]
+pub struct FuzzHasher {
+    mode: Mode,
+    // The FNV-1a hash of the bytes written so far.
+    state: u64,
+}
+
+impl Hasher for FuzzHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = (self.state ^ u64::from(byte)).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self.mode {
+            Mode::Seeded(seed) => mix(self.state ^ seed),
+            Mode::Constant(hash) => hash,
+            Mode::LowBits { bits, seed } => mix(self.state ^ seed) & u64::MAX.checked_shr(64 - bits).unwrap_or(0),
+            #[allow(clippy::cast_possible_truncation)]
+            Mode::Chosen(ref hashes) => match hashes.len() {
+                0 => 0,
+                len => hashes[(self.state % len as u64) as usize],
+            },
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! The panic hook handling failures.
+//!
+//! Outside of `fuzzing_debug` builds, the first operation executed installs
+//! a hook aborting the process on a [`Failure`], so that the fuzzer records
+//! a crash, unless one was installed with [`hook`] beforehand. The builder
+//! picks what happens on a failure and whether the hook installed before,
+//! such as that of `better_panic` or of the fuzzing engine, still runs.
+//! The panics other than the failures are reported, by the previous hook or
+//! otherwise to the standard error, save for those the generated code
+//! catches and compares, and those the generated elements and closures
+//! raise on purpose.
+//!
+//! ```no_run
+//! use rutenspitz::hook::Action;
+//!
+//! rutenspitz::hook()
+//!     .on_failure(Action::Record)
+//!     .chain_previous(true)
+//!     .install();
+//! ```
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::Failure;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // Whether the next panic on the thread is raised on purpose.
+    static DELIBERATE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Marks the next panic on the thread as raised on purpose by a generated
+/// element or closure, so that the hook does not report it on its own.
+pub(crate) fn deliberate() {
+    DELIBERATE.with(|deliberate| deliberate.set(true));
+}
+
+/// What the hook does on a failure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Writes the most recent operations out and aborts the process.
+    Abort,
+    /// Writes the most recent operations out and lets the panic unwind.
+    Record,
+    /// Lets the panic unwind.
+    Continue,
+}
+
+/// A builder of the panic hook, see [`hook`].
+#[derive(Clone, Copy, Debug)]
+pub struct Builder {
+    on_failure: Action,
+    chain_previous: bool,
+}
+
+/// Starts building the panic hook. By default, it aborts on a failure and
+/// does not run the previous hook, like the one installed by the generated
+/// code.
+#[must_use]
+pub fn hook() -> Builder {
+    Builder {
+        on_failure: Action::Abort,
+        chain_previous: false,
+    }
+}
+
+/// Whether a hook was installed with [`Builder::install`].
+#[must_use]
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+impl Builder {
+    /// What to do on a failure.
+    #[must_use]
+    pub fn on_failure(self, action: Action) -> Self {
+        Self {
+            on_failure: action,
+            ..self
+        }
+    }
+
+    /// Whether to run the hook installed before on the panics that the
+    /// generated code does not catch, before acting on failures. Otherwise,
+    /// the default, the failures are only reported as the action says and
+    /// the other panics are written to the standard error. The hooks of
+    /// libFuzzer and honggfuzz abort on every panic, so they must not be
+    /// chained.
+    #[must_use]
+    pub fn chain_previous(self, chain_previous: bool) -> Self {
+        Self {
+            chain_previous,
+            ..self
+        }
+    }
+
+    /// Installs the hook in place of the current one.
+    pub fn install(self) {
+        let previous = std::panic::take_hook();
+        let Self {
+            on_failure,
+            chain_previous,
+        } = self;
+        INSTALLED.store(true, Ordering::SeqCst);
+        std::panic::set_hook(Box::new(move |panic_info| {
+            let deliberate = DELIBERATE.with(|deliberate| deliberate.replace(false));
+            // The panics of the model and the tested instance are expected,
+            // the generated code compares them.
+            let compared = crate::elements::comparing();
+            if chain_previous && !compared {
+                previous(panic_info);
+            }
+            let Some(failure) = panic_info.payload().downcast_ref::<Failure>() else {
+                if !chain_previous && !deliberate && !compared {
+                    eprintln!("{panic_info}");
+                }
+                return;
+            };
+            match on_failure {
+                Action::Abort => {
+                    crate::recent::dump(failure);
+                    std::process::abort();
+                }
+                Action::Record => crate::recent::dump(failure),
+                Action::Continue => {}
+            }
+        }));
+    }
+}
@@ -1,23 +1,92 @@
+#![allow(clippy::non_std_lazy_statics)]
+
 pub use rutenspitz_macro::arbitrary_stateful_operations;
 
-lazy_static::lazy_static! {
-    pub static ref NON_DEBUG_PANIC_HOOK: () = {
-        std::panic::set_hook(Box::new(|panic_info| {
-            if panic_info.payload().is::<crate::OutcomePanic>() {
-                std::process::abort();
-            }
-        }));
-    };
+#[cfg(feature = "allocations")]
+pub mod allocations;
+pub mod check;
+pub mod closures;
+#[cfg(feature = "complexity")]
+pub mod complexity;
+#[cfg(feature = "serde")]
+pub mod corpus;
+pub mod domain;
+pub mod elements;
+pub mod failure;
+pub mod fork;
+pub mod fuzz;
+pub mod hashing;
+pub mod hook;
+pub mod linearizability;
+pub mod minimize;
+#[cfg(feature = "proptest")]
+pub mod property;
+pub mod recent;
+pub mod regression;
+pub mod replay;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod trace;
+pub mod transaction;
+
+pub use check::check;
+pub use failure::Failure;
+pub use hook::hook;
+
+/// An operation that can be executed on a model and a tested instance,
+/// comparing the results.
+///
+/// Generated for the `Op` enum of every specification.
+pub trait Operation<M, T>: std::fmt::Display {
+    fn execute_and_compare(self, model: &mut M, tested: &mut T);
 }
 
+/// Raises a failure with the formatted message.
+#[deprecated(note = "raise a `rutenspitz::Failure` with `rutenspitz::failure::fail` instead")]
 #[macro_export]
 macro_rules! panic {
-    ($($arg:tt)*) => {  std::panic::panic_any(rutenspitz::OutcomePanic(format!($($arg)*))) };
+    ($($arg:tt)*) => {
+        $crate::failure::fail($crate::Failure::Other {
+            message: format!($($arg)*),
+        })
+    };
 }
 
+/// The payload of the panics raised by the generated code before
+/// [`Failure`].
+#[deprecated(note = "the failures are raised as `rutenspitz::Failure`")]
 pub struct OutcomePanic(pub String);
 
+#[allow(deprecated)]
+impl From<OutcomePanic> for Failure {
+    fn from(outcome: OutcomePanic) -> Self {
+        Self::Other { message: outcome.0 }
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Installs the default panic hook, aborting on failures, unless one was
+    /// installed with [`hook()`]. Initialized by the first operation executed
+    /// outside of `fuzzing_debug` builds.
+    pub static ref NON_DEBUG_PANIC_HOOK: () = {
+        if !hook::installed() {
+            hook().install();
+        }
+    };
+}
+
+#[cfg(feature = "afl")]
+pub use afl;
+pub use arbitrary;
+#[cfg(feature = "honggfuzz")]
+pub use honggfuzz;
 pub use lazy_static;
+#[cfg(feature = "libfuzzer-sys")]
+pub use libfuzzer_sys;
+#[cfg(feature = "proptest")]
+pub use proptest;
+#[cfg(feature = "serde")]
+pub use serde;
 
 pub mod derive {
     pub use arbitrary::Arbitrary;
@@ -0,0 +1,298 @@
+//! Linearizability checking of structures shared between threads.
+//!
+//! The operations are split across several threads that run them against a
+//! single shared tested instance. Every call is stamped with the times of its
+//! invocation and return, which makes up a [`History`]. The history is
+//! linearizable if there is a sequential order of the calls, consistent with
+//! the order of those that did not overlap in time, in which the model
+//! returns what the tested instance did. The order is searched for with the
+//! algorithm of Wing and Gong, as refined by Lowe: the pairs of a set of
+//! calls already linearized and a state of the model that led nowhere are
+//! remembered, so that the search does not explore them again. The model
+//! has to be `Hash` and `Eq` for that.
+
+#[cfg(feature = "loom")]
+pub mod loom;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Barrier;
+
+/// An operation that can be executed on a tested instance shared between
+/// threads.
+///
+/// Generated for the `Op` enum of a specification with `concurrent = true`.
+pub trait Operation<M, T>: fmt::Display {
+    /// Executes the operation on the tested instance and records its outcome.
+    /// The outcome does not borrow the operation.
+    fn execute_concurrently<'a>(&self, tested: &T) -> Observed<'a, M>
+    where
+        Self: 'a;
+}
+
+/// The outcome of a call on the tested instance, along with a way to tell
+/// whether the model agrees with it.
+pub struct Observed<'a, M> {
+    outcome: String,
+    check: Box<dyn Fn(&mut M) -> bool + Send + 'a>,
+}
+
+impl<'a, M> Observed<'a, M> {
+    /// Records the value returned by the tested instance, `None` if it
+    /// panicked, and the same call on the model.
+    pub fn new<R, F>(tested_ret_value: Option<R>, model_call: F) -> Self
+    where
+        R: fmt::Debug + PartialEq + Send + 'a,
+        F: Fn(&mut M) -> R + Send + 'a,
+    {
+        let outcome = match tested_ret_value {
+            Some(ref value) => format!("{value:?}"),
+            None => "panicked".to_owned(),
+        };
+        Self {
+            outcome,
+            check: Box::new(move |model| {
+                let model_ret_value = catch_unwind(AssertUnwindSafe(|| model_call(model))).ok();
+                model_ret_value == tested_ret_value
+            }),
+        }
+    }
+}
+
+// The times of the invocation and return of a call and its outcome.
+type Stamp<'a, M> = (u64, u64, Observed<'a, M>);
+
+struct Call<'a, M> {
+    thread: usize,
+    op: &'a (dyn fmt::Display + Sync),
+    invoked: u64,
+    returned: u64,
+    observed: Observed<'a, M>,
+}
+
+/// The calls made by all the threads with the times of their invocation and
+/// return.
+pub struct History<'a, M> {
+    calls: Vec<Call<'a, M>>,
+    // Calls returning later than this are considered pending: they may or
+    // may not have taken effect and their outcome is disregarded.
+    cut: u64,
+}
+
+impl<'a, M> History<'a, M> {
+    /// Runs each of the sequences of operations on its own thread against
+    /// the shared tested instance.
+    pub fn record<T, O>(tested: &T, threads: &'a [Vec<O>]) -> Self
+    where
+        T: Sync,
+        O: Operation<M, T> + Sync,
+    {
+        let clock = AtomicU64::new(0);
+        let barrier = Barrier::new(threads.len());
+
+        let stamps = std::thread::scope(|scope| {
+            let handles: Vec<_> = threads
+                .iter()
+                .map(|ops| {
+                    let (clock, barrier) = (&clock, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        ops.iter()
+                            .map(|op| {
+                                let invoked = clock.fetch_add(1, Ordering::SeqCst);
+                                let observed = op.execute_concurrently(tested);
+                                let returned = clock.fetch_add(1, Ordering::SeqCst);
+                                (invoked, returned, observed)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(stamps) => stamps,
+                    Err(payload) => std::panic::resume_unwind(payload),
+                })
+                .collect()
+        });
+
+        Self::from_stamps(threads, stamps)
+    }
+
+    /// Pairs the outcomes of the calls made by each thread, along with the
+    /// times of their invocation and return, with the operations.
+    fn from_stamps<O>(threads: &'a [Vec<O>], stamps: Vec<Vec<Stamp<'a, M>>>) -> Self
+    where
+        O: fmt::Display + Sync,
+    {
+        let calls = threads
+            .iter()
+            .zip(stamps)
+            .enumerate()
+            .flat_map(|(thread, (ops, stamps))| {
+                ops.iter()
+                    .zip(stamps)
+                    .map(move |(op, (invoked, returned, observed))| Call {
+                        thread,
+                        op: op as &(dyn fmt::Display + Sync),
+                        invoked,
+                        returned,
+                        observed,
+                    })
+            })
+            .collect();
+
+        Self {
+            calls,
+            cut: u64::MAX,
+        }
+    }
+
+    fn is_present(&self, call: &Call<'a, M>) -> bool {
+        call.invoked < self.cut
+    }
+
+    fn is_pending(&self, call: &Call<'a, M>) -> bool {
+        call.returned > self.cut
+    }
+}
+
+impl<M: Clone + Hash + Eq> History<'_, M> {
+    /// Searches for a sequential order of the calls that explains their
+    /// outcomes, starting from the given state of the model. Returns the
+    /// indices of the calls in that order; pending calls may be left out.
+    pub fn linearize(&self, model: &M) -> Option<Vec<usize>> {
+        let mut linearized: Vec<_> = self.calls.iter().map(|call| !self.is_present(call)).collect();
+        let mut order = vec![];
+        let mut explored = HashSet::new();
+        self.search(model, &mut linearized, &mut order, &mut explored)
+            .then_some(order)
+    }
+
+    // `explored` holds the calls linearized and the states of the model that
+    // were searched from already, to no avail.
+    fn search(
+        &self,
+        model: &M,
+        linearized: &mut [bool],
+        order: &mut Vec<usize>,
+        explored: &mut HashSet<(Vec<bool>, M)>,
+    ) -> bool {
+        if !explored.insert((linearized.to_vec(), model.clone())) {
+            return false;
+        }
+
+        // No call can be linearized after one that had returned before it
+        // was invoked.
+        let horizon = self
+            .calls
+            .iter()
+            .zip(linearized.iter())
+            .filter(|(call, &done)| !done && !self.is_pending(call))
+            .map(|(call, _)| call.returned)
+            .min();
+        let Some(horizon) = horizon else {
+            return true;
+        };
+
+        for (index, call) in self.calls.iter().enumerate() {
+            if linearized[index] || call.invoked > horizon {
+                continue;
+            }
+
+            let mut next = model.clone();
+            if !(call.observed.check)(&mut next) && !self.is_pending(call) {
+                continue;
+            }
+
+            linearized[index] = true;
+            order.push(index);
+            if self.search(&next, linearized, order, explored) {
+                return true;
+            }
+            order.pop();
+            linearized[index] = false;
+        }
+
+        false
+    }
+
+    /// Returns the shortest prefix of the history, ending with the return of
+    /// some call, that is not linearizable. Every prefix of a linearizable
+    /// history is linearizable too, so the calls in it alone are to blame.
+    /// The calls that had been invoked but had not returned by the end of the
+    /// prefix are pending: they may or may not have taken effect.
+    ///
+    /// For the same reason, the prefix is bisected.
+    pub fn minimal_failing_prefix(mut self, model: &M) -> Option<Self> {
+        let mut returns: Vec<_> = self.calls.iter().map(|call| call.returned).collect();
+        returns.sort_unstable();
+
+        let failing = returns.partition_point(|&cut| {
+            self.cut = cut;
+            self.linearize(model).is_some()
+        });
+        let &cut = returns.get(failing)?;
+        self.cut = cut;
+        self.calls.retain(|call| call.invoked < cut);
+        Some(self)
+    }
+}
+
+impl<M: Clone + Hash + Eq> History<'_, M> {
+    /// Panics with the shortest prefix of the history that is not
+    /// linearizable, if the history is not.
+    fn assert_linearizable(self, model: &M) {
+        if self.linearize(model).is_some() {
+            return;
+        }
+
+        let history = self.minimal_failing_prefix(model).unwrap_or_else(|| {
+            unreachable!("a history that is not linearizable has a prefix that is not")
+        });
+        crate::failure::fail(crate::Failure::NotLinearizable {
+            history: history.to_string(),
+        });
+    }
+}
+
+impl<M> fmt::Display for History<'_, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut calls: Vec<_> = self.calls.iter().collect();
+        calls.sort_by_key(|call| call.invoked);
+        for call in calls {
+            write!(f, "[thread {}] ", call.thread)?;
+            if self.is_pending(call) {
+                writeln!(f, "{}..  {}  // pending", call.invoked, call.op)?;
+            } else {
+                writeln!(
+                    f,
+                    "{}..{}  {}  // {}",
+                    call.invoked, call.returned, call.op, call.observed.outcome
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs each of the sequences of operations on its own thread against the
+/// shared tested instance and checks that the resulting history is
+/// linearizable with respect to the model. Otherwise panics with the shortest
+/// prefix of the history that is not.
+pub fn check<M, T, O>(model: &M, tested: &T, threads: &[Vec<O>])
+where
+    M: Clone + Hash + Eq,
+    T: Sync,
+    O: Operation<M, T> + Sync,
+{
+    #[cfg(not(fuzzing_debug))]
+    lazy_static::initialize(&crate::NON_DEBUG_PANIC_HOOK);
+
+    History::record(tested, threads).assert_linearizable(model);
+}
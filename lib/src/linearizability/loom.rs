@@ -0,0 +1,77 @@
+//! Exhaustive exploration of the interleavings of the threads with
+//! [`loom`](https://docs.rs/loom).
+//!
+//! The tested structure has to be built on the synchronization primitives of
+//! `loom` for the interleavings to be explored, as usual. Loom allows at most
+//! four threads besides the main one.
+//!
+//! The number of interleavings grows quickly with the length of the
+//! sequences, so unless `LOOM_MAX_PREEMPTIONS` says otherwise, only those
+//! with at most [`DEFAULT_PREEMPTION_BOUND`] preemptions are explored.
+
+use super::{History, Operation};
+
+use loom::sync::atomic::{AtomicU64, Ordering};
+use loom::sync::Arc;
+
+use std::hash::Hash;
+
+pub const DEFAULT_PREEMPTION_BOUND: usize = 3;
+
+/// Runs each of the sequences of operations on its own thread against a
+/// fresh tested instance, for every interleaving of the threads that `loom`
+/// explores, and checks that each of the resulting histories is linearizable
+/// with respect to the model. Otherwise panics with the shortest prefix of
+/// the first history that is not.
+pub fn check<M, T, O, F>(model: M, new_tested: F, threads: Vec<Vec<O>>)
+where
+    M: Clone + Hash + Eq + Send + Sync + 'static,
+    T: 'static,
+    O: Operation<M, T> + Send + Sync + 'static,
+    F: Fn() -> T + Send + Sync + 'static,
+{
+    #[cfg(not(fuzzing_debug))]
+    lazy_static::initialize(&crate::NON_DEBUG_PANIC_HOOK);
+
+    let mut builder = loom::model::Builder::new();
+    builder
+        .preemption_bound
+        .get_or_insert(DEFAULT_PREEMPTION_BOUND);
+
+    let threads = std::sync::Arc::new(threads);
+    builder.check(move || {
+        let tested = Arc::new(new_tested());
+        // A call that returned before another one was invoked happens
+        // before it, as it would for an observer of both.
+        let clock = Arc::new(AtomicU64::new(0));
+
+        let handles: Vec<_> = (0..threads.len())
+            .map(|thread| {
+                let threads = std::sync::Arc::clone(&threads);
+                let tested = Arc::clone(&tested);
+                let clock = Arc::clone(&clock);
+                loom::thread::spawn(move || {
+                    threads[thread]
+                        .iter()
+                        .map(|op| {
+                            let invoked = clock.fetch_add(1, Ordering::SeqCst);
+                            let observed = op.execute_concurrently(&*tested);
+                            let returned = clock.fetch_add(1, Ordering::SeqCst);
+                            (invoked, returned, observed)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let stamps = handles
+            .into_iter()
+            .map(|handle| match handle.join() {
+                Ok(stamps) => stamps,
+                Err(payload) => std::panic::resume_unwind(payload),
+            })
+            .collect();
+
+        History::from_stamps(&threads, stamps).assert_linearizable(&model);
+    });
+}
@@ -0,0 +1,360 @@
+//! Minimization of failing sequences of operations.
+//!
+//! [`minimize`] reduces a failing sequence to a smaller one that still fails
+//! the same way, by delta debugging: it drops chunks of the sequence, halving
+//! their length down to single operations, and then replaces the arguments
+//! of the remaining operations with the simpler values given by [`Shrink`].
+//! Every candidate is run against a fresh model and tested instance.
+//!
+//! `Shrink` is implemented for the `Op` enum of every specification whose
+//! argument types all implement it.
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::mem::Discriminant;
+use std::ops::{Range, RangeInclusive};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::closures::Closure;
+use crate::elements;
+use crate::failure::{self, Failure};
+use crate::Operation;
+
+/// A value that can be replaced with simpler ones while minimizing.
+pub trait Shrink: Sized {
+    /// The simpler values to try in place of this one, simplest first. Each
+    /// of them has to be strictly simpler, so that shrinking terminates.
+    /// There are none by default.
+    fn shrink(&self) -> Vec<Self> {
+        vec![]
+    }
+}
+
+macro_rules! impl_shrink_unsigned {
+    ($($ty:ty),*) => {
+        $(
+            impl Shrink for $ty {
+                fn shrink(&self) -> Vec<Self> {
+                    let mut values = vec![];
+                    for value in [0, *self / 2, self.saturating_sub(1)] {
+                        if value != *self && !values.contains(&value) {
+                            values.push(value);
+                        }
+                    }
+                    values
+                }
+            }
+        )*
+    };
+}
+
+impl_shrink_unsigned!(u8, u16, u32, u64, u128, usize);
+
+macro_rules! impl_shrink_signed {
+    ($($ty:ty),*) => {
+        $(
+            impl Shrink for $ty {
+                fn shrink(&self) -> Vec<Self> {
+                    let mut values = vec![];
+                    for value in [0, *self / 2, *self - self.signum()] {
+                        if value != *self && !values.contains(&value) {
+                            values.push(value);
+                        }
+                    }
+                    values
+                }
+            }
+        )*
+    };
+}
+
+impl_shrink_signed!(i8, i16, i32, i64, i128, isize);
+
+impl Shrink for bool {
+    fn shrink(&self) -> Vec<Self> {
+        if *self {
+            vec![false]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl Shrink for char {}
+
+impl Shrink for String {
+    fn shrink(&self) -> Vec<Self> {
+        let chars: Vec<char> = self.chars().collect();
+        let mut values = vec![];
+        for len in [0, chars.len() / 2, chars.len().saturating_sub(1)] {
+            let value: String = chars[..len].iter().collect();
+            if len < chars.len() && !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for Vec<T> {
+    fn shrink(&self) -> Vec<Self> {
+        if self.is_empty() {
+            return vec![];
+        }
+        let mut values = vec![vec![]];
+        if self.len() > 2 {
+            values.push(self[..self.len() / 2].to_vec());
+        }
+        if self.len() > 1 {
+            for index in 0..self.len() {
+                let mut value = self.clone();
+                value.remove(index);
+                values.push(value);
+            }
+        }
+        for (index, item) in self.iter().enumerate() {
+            for simpler in item.shrink() {
+                let mut value = self.clone();
+                value[index] = simpler;
+                values.push(value);
+            }
+        }
+        values
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for Box<[T]> {
+    fn shrink(&self) -> Vec<Self> {
+        self.to_vec().shrink().into_iter().map(Vec::into_boxed_slice).collect()
+    }
+}
+
+impl<T: Shrink> Shrink for Box<T> {
+    fn shrink(&self) -> Vec<Self> {
+        (**self).shrink().into_iter().map(Box::new).collect()
+    }
+}
+
+impl<T: Shrink> Shrink for Option<T> {
+    fn shrink(&self) -> Vec<Self> {
+        match self {
+            None => vec![],
+            Some(value) => std::iter::once(None).chain(value.shrink().into_iter().map(Some)).collect(),
+        }
+    }
+}
+
+impl<A: Clone + Shrink, B: Clone + Shrink> Shrink for (A, B) {
+    fn shrink(&self) -> Vec<Self> {
+        let (a, b) = self;
+        let firsts = a.shrink().into_iter().map(|a| (a, b.clone()));
+        let seconds = b.shrink().into_iter().map(|b| (a.clone(), b));
+        firsts.chain(seconds).collect()
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for Range<T> {
+    fn shrink(&self) -> Vec<Self> {
+        (self.start.clone(), self.end.clone())
+            .shrink()
+            .into_iter()
+            .map(|(start, end)| start..end)
+            .collect()
+    }
+}
+
+impl<T: Clone + Shrink> Shrink for RangeInclusive<T> {
+    fn shrink(&self) -> Vec<Self> {
+        (self.start().clone(), self.end().clone())
+            .shrink()
+            .into_iter()
+            .map(|(start, end)| start..=end)
+            .collect()
+    }
+}
+
+/// A closure that panics is simplified into one that does not; shapes are
+/// kept as they are.
+impl<S: Clone> Shrink for Closure<S> {
+    fn shrink(&self) -> Vec<Self> {
+        match self.panic_on_call {
+            None => vec![],
+            Some(_) => vec![Self {
+                shape: self.shape.clone(),
+                panic_on_call: None,
+            }],
+        }
+    }
+}
+
+/// How a sequence fails: the kind of failure and the name of the operation
+/// it happened on, or the message of a panic not raised as a failure.
+#[derive(PartialEq)]
+enum Signature {
+    Failure(Discriminant<Failure>, Option<&'static str>),
+    Panic(String),
+}
+
+/// Runs the operations against a fresh model and tested instance. Returns
+/// how they failed, if they did, along with the index of the failing
+/// operation, if known.
+fn run<M, T, O, F>(new: &F, ops: &[O]) -> Option<(Signature, Option<usize>)>
+where
+    O: Operation<M, T> + Clone,
+    F: Fn() -> (M, T),
+{
+    failure::reset_op_index();
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let (mut model, mut tested) = new();
+        for op in ops {
+            op.clone().execute_and_compare(&mut model, &mut tested);
+        }
+        drop((model, tested));
+        elements::check_drops();
+    }));
+    let payload = result.err()?;
+    Some(match payload.downcast_ref::<Failure>() {
+        Some(failure) => (
+            Signature::Failure(std::mem::discriminant(failure), failure.op_name()),
+            failure.op_index(),
+        ),
+        None => (Signature::Panic(failure::panic_message(&*payload)), None),
+    })
+}
+
+struct Minimizer<'f, O, F> {
+    new: &'f F,
+    signature: Signature,
+    ops: Vec<O>,
+}
+
+impl<O: Clone + Shrink, F> Minimizer<'_, O, F> {
+    /// Keeps the candidate if it fails the same way, dropping the operations
+    /// following the failing one.
+    fn try_candidate<M, T>(&mut self, mut candidate: Vec<O>) -> bool
+    where
+        O: Operation<M, T>,
+        F: Fn() -> (M, T),
+    {
+        match run(self.new, &candidate) {
+            Some((signature, index)) if signature == self.signature => {
+                if let Some(index) = index {
+                    candidate.truncate(index + 1);
+                }
+                self.ops = candidate;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn drop_chunks<M, T>(&mut self) -> bool
+    where
+        O: Operation<M, T>,
+        F: Fn() -> (M, T),
+    {
+        let mut progress = false;
+        let mut len = self.ops.len() / 2;
+        while len > 0 {
+            let mut start = 0;
+            while start < self.ops.len() {
+                let end = (start + len).min(self.ops.len());
+                let mut candidate = self.ops.clone();
+                candidate.drain(start..end);
+                if self.try_candidate(candidate) {
+                    progress = true;
+                } else {
+                    start += len;
+                }
+            }
+            len /= 2;
+        }
+        progress
+    }
+
+    fn shrink_ops<M, T>(&mut self) -> bool
+    where
+        O: Operation<M, T>,
+        F: Fn() -> (M, T),
+    {
+        let mut progress = false;
+        let mut index = 0;
+        while index < self.ops.len() {
+            let simpler = self.ops[index].shrink().into_iter().find_map(|op| {
+                let mut candidate = self.ops.clone();
+                candidate[index] = op;
+                self.try_candidate(candidate).then_some(())
+            });
+            if simpler.is_some() {
+                progress = true;
+            } else {
+                index += 1;
+            }
+        }
+        progress
+    }
+}
+
+/// Reduces a failing sequence of operations, run against the model and the
+/// tested instance returned by `new`, to a smaller one that fails the same
+/// way: with the same kind of failure on an operation of the same name.
+/// Returns `None` if the sequence does not fail.
+///
+/// The panics are silenced meanwhile, on all the threads since the panic
+/// hook is global to the process, and no regression test is written.
+pub fn minimize<M, T, O, F>(ops: Vec<O>, new: F) -> Option<Vec<O>>
+where
+    O: Operation<M, T> + Clone + Shrink,
+    F: Fn() -> (M, T),
+{
+    failure::silently(|| {
+        let (signature, index) = run(&new, &ops)?;
+        let mut minimizer = Minimizer {
+            new: &new,
+            signature,
+            ops,
+        };
+        if let Some(index) = index {
+            minimizer.ops.truncate(index + 1);
+        }
+        while minimizer.drop_chunks() | minimizer.shrink_ops() {}
+        Some(minimizer.ops)
+    })
+}
+
+/// Decodes the operations from what remains of the input, the way the
+/// fuzzing loops do.
+///
+/// # Errors
+///
+/// Fails if an operation cannot be decoded.
+pub fn decode<'a, O: Arbitrary<'a>>(ring: &mut Unstructured<'a>) -> arbitrary::Result<Vec<O>> {
+    let mut ops = vec![];
+    while !ring.is_empty() {
+        ops.push(O::arbitrary(ring)?);
+    }
+    Ok(ops)
+}
+
+/// Minimizes the sequence of operations a failing input decodes to, see
+/// [`minimize`]. `new` builds the model and the tested instance from the
+/// start of the input, the way the fuzzing loop does, and the operations are
+/// decoded from the rest.
+///
+/// # Errors
+///
+/// Fails if the input cannot be decoded.
+pub fn minimize_input<'a, M, T, O, F>(data: &'a [u8], new: F) -> arbitrary::Result<Option<Vec<O>>>
+where
+    O: Operation<M, T> + Arbitrary<'a> + Clone + Shrink,
+    F: Fn(&mut Unstructured<'a>) -> arbitrary::Result<(M, T)>,
+{
+    let mut ring = Unstructured::new(data);
+    drop(new(&mut ring)?);
+    let ops = decode(&mut ring)?;
+
+    Ok(minimize(ops, || {
+        new(&mut Unstructured::new(data))
+            .unwrap_or_else(|_| unreachable!("the start of the input has been decoded already"))
+    }))
+}
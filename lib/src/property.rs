@@ -0,0 +1,128 @@
+//! Property-based testing with proptest, with the `proptest` feature.
+//!
+//! The `Op` enum generated for every specification implements
+//! `proptest::arbitrary::Arbitrary`, so `any::<op::Op<..>>()` generates
+//! operations and `proptest::collection::vec` sequences of them, which
+//! proptest shrinks to fewer and simpler operations. An argument takes the
+//! strategy given in the specification with `#[strategy(...)]`, or is
+//! decoded with its `arbitrary::Arbitrary` implementation from random bytes,
+//! which shrink towards simpler values.
+//!
+//! [`run`] works like `prop_state_machine!`: it runs sequences on fresh
+//! models and tested instances and, on a failure, replays the minimal one
+//! proptest shrank it to.
+//!
+//! ```ignore
+//! let config = rutenspitz::proptest::test_runner::Config::with_cases(1000);
+//! rutenspitz::property::run::<_, _, op::Op<u16, u16>>(config, 64, || {
+//!     (ModelHashMap::new(), HashMap::new())
+//! })
+//! .unwrap();
+//! ```
+
+use proptest::arbitrary::{any, Arbitrary};
+use proptest::collection::vec;
+use proptest::strategy::Strategy;
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::elements;
+use crate::failure::{self, Failure};
+use crate::replay::{self, Report};
+use crate::Operation;
+
+/// How many random bytes at most an argument is decoded from.
+pub const ARGUMENT_BYTES: usize = 64;
+
+/// How many operations at most a handle gets.
+pub const HANDLE_OPS: usize = 16;
+
+/// Values of a type decoded with its `arbitrary::Arbitrary` implementation
+/// from random bytes, the default strategy of the arguments.
+pub fn decoded<T>() -> impl Strategy<Value = T>
+where
+    T: for<'a> arbitrary::Arbitrary<'a> + fmt::Debug,
+{
+    vec(any::<u8>(), 0..=ARGUMENT_BYTES).prop_filter_map("the bytes could not be decoded", |bytes| {
+        T::arbitrary_take_rest(arbitrary::Unstructured::new(&bytes)).ok()
+    })
+}
+
+/// The operations on a handle. Used by the generated code.
+#[doc(hidden)]
+pub fn handle_ops<O: Arbitrary>() -> impl Strategy<Value = Vec<O>> {
+    vec(any::<O>(), 0..=HANDLE_OPS)
+}
+
+/// Why [`run`] failed.
+pub enum Error<O> {
+    /// A sequence failed. These are the operations it was shrunk to, and
+    /// the report of their replay.
+    Failed { ops: Vec<O>, report: Report },
+    /// Proptest gave up, such as on too many values rejected.
+    Aborted(String),
+}
+
+impl<O> fmt::Display for Error<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failed { report, .. } => write!(f, "The minimal failing sequence:\n{report}"),
+            Self::Aborted(reason) => write!(f, "Proptest gave up: {reason}"),
+        }
+    }
+}
+
+// Like `Display`, so that unwrapping the result of a test prints the report.
+impl<O> fmt::Debug for Error<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl<O> std::error::Error for Error<O> {}
+
+/// Runs as many sequences of up to `max_ops` operations as the
+/// configuration has cases, each on the model and the tested instance `new`
+/// builds, and shrinks the first one failing.
+///
+/// The panics are silenced meanwhile, on all the threads since the panic
+/// hook is global to the process, and no regression test is written.
+///
+/// # Errors
+///
+/// Fails with the minimal failing sequence, or if proptest gives up.
+pub fn run<M, T, O>(config: Config, max_ops: usize, new: impl Fn() -> (M, T)) -> Result<(), Error<O>>
+where
+    O: Operation<M, T> + Arbitrary + Clone,
+{
+    let mut runner = TestRunner::new(config);
+    let strategy = vec(any::<O>(), 0..=max_ops);
+    let result = failure::silently(|| {
+        runner.run(&strategy, |ops| {
+            failure::reset_op_index();
+            let (mut model, mut tested) = new();
+            catch_unwind(AssertUnwindSafe(move || {
+                for op in ops {
+                    op.execute_and_compare(&mut model, &mut tested);
+                }
+                drop((model, tested));
+                elements::check_drops();
+            }))
+            .map_err(|payload| match payload.downcast::<Failure>() {
+                Ok(failure) => TestCaseError::fail(failure.to_string()),
+                Err(payload) => TestCaseError::fail(failure::panic_message(&*payload)),
+            })
+        })
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(TestError::Fail(_, ops)) => {
+            let (model, tested) = new();
+            let report = replay::replay(ops.clone(), model, tested);
+            Err(Error::Failed { ops, report })
+        }
+        Err(TestError::Abort(reason)) => Err(Error::Aborted(reason.to_string())),
+    }
+}
@@ -0,0 +1,136 @@
+//! The most recent operations executed on the thread, kept in all builds.
+//!
+//! Outside of `fuzzing_debug` builds there is no trace, so the generated
+//! code keeps the index and the text of the last [`CAPACITY`] operations,
+//! with their arguments, in a ring buffer instead. The formatting of each
+//! stops at [`MAX_LEN`] bytes, however large its arguments, and the buffers
+//! are reused once the ring is full, so that it costs no allocation past
+//! the first operations. When a
+//! failure aborts the process, the panic hook writes them out along with
+//! the failure: to a file named after the hash of the input in the
+//! directory given by [`DIRECTORY_VAR`] if it is set, to the standard error
+//! otherwise.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::{self, Write as _};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use crate::Failure;
+
+/// How many operations are kept.
+pub const CAPACITY: usize = 64;
+
+/// How many bytes of the text of an operation are kept.
+pub const MAX_LEN: usize = 256;
+
+/// The directory the operations are written to on a failure, if set.
+pub const DIRECTORY_VAR: &str = "RUTENSPITZ_RECENT";
+
+/// An operation executed, as its index in the sequence and its text, as
+/// the operation prints, cut at [`MAX_LEN`] bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub index: usize,
+    pub operation: String,
+}
+
+struct Ring {
+    entries: VecDeque<Entry>,
+    input_hash: Option<u64>,
+}
+
+thread_local! {
+    static RING: RefCell<Ring> = const {
+        RefCell::new(Ring {
+            entries: VecDeque::new(),
+            input_hash: None,
+        })
+    };
+}
+
+/// Starts keeping the operations of a new sequence, decoded from the given
+/// input, which the file written on a failure is named after.
+pub fn start(data: &[u8]) {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.hash(&mut hasher);
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        ring.entries.clear();
+        ring.input_hash = Some(hasher.finish());
+    });
+}
+
+/// Keeps an operation about to be executed. Called by the generated code.
+#[doc(hidden)]
+pub fn push(index: usize, operation: &dyn fmt::Display) {
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        // The oldest entry is reused once the ring is full.
+        let oldest = if ring.entries.len() < CAPACITY { None } else { ring.entries.pop_front() };
+        let mut entry = oldest.unwrap_or(Entry {
+            index,
+            operation: String::with_capacity(MAX_LEN + '…'.len_utf8()),
+        });
+        entry.index = index;
+        entry.operation.clear();
+        if write!(Bounded(&mut entry.operation), "{operation}").is_err() {
+            entry.operation.push('…');
+        }
+        ring.entries.push_back(entry);
+    });
+}
+
+// Writes up to `MAX_LEN` bytes, failing past them so that the formatting
+// stops there.
+struct Bounded<'s>(&'s mut String);
+
+impl fmt::Write for Bounded<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let room = MAX_LEN - self.0.len();
+        if text.len() <= room {
+            self.0.push_str(text);
+            return Ok(());
+        }
+        let end = (0..=room).rev().find(|&end| text.is_char_boundary(end)).unwrap_or(0);
+        self.0.push_str(&text[..end]);
+        Err(fmt::Error)
+    }
+}
+
+/// The operations kept, oldest first.
+#[must_use]
+pub fn entries() -> Vec<Entry> {
+    RING.with(|ring| ring.borrow().entries.iter().cloned().collect())
+}
+
+/// The failure followed by the operations kept.
+#[must_use]
+pub fn render(failure: &Failure) -> String {
+    let entries = entries();
+    let mut text = format!("{failure}\nThe last {} operations, oldest first:\n", entries.len());
+    for entry in entries {
+        let _ = writeln!(text, "    #{} {}", entry.index, entry.operation);
+    }
+    text
+}
+
+/// Writes the failure and the operations kept out, to a file if
+/// [`DIRECTORY_VAR`] is set and the input is known, to the standard error
+/// otherwise. Called by the panic hook before aborting.
+pub fn dump(failure: &Failure) {
+    let text = render(failure);
+    let input_hash = RING.with(|ring| ring.try_borrow().ok().and_then(|ring| ring.input_hash));
+    if let (Some(directory), Some(input_hash)) = (std::env::var_os(DIRECTORY_VAR), input_hash) {
+        let directory = PathBuf::from(directory);
+        let path = directory.join(format!("{input_hash:016x}.txt"));
+        if std::fs::create_dir_all(&directory)
+            .and_then(|()| std::fs::write(&path, &text))
+            .is_ok()
+        {
+            return;
+        }
+    }
+    eprint!("{text}");
+}
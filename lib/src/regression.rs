@@ -0,0 +1,252 @@
+//! Standalone regression tests written out on failure.
+//!
+//! While a recording is active on a thread, the steps of the sequence are
+//! kept along with what the model returned for each of them. When the
+//! sequence fails, they are written out as a `#[test]` function that builds
+//! the tested instance, replays the steps on it and checks each return value
+//! against the model, ready to be dropped into a test suite.
+//!
+//! The return values are compared through their `Debug` renderings, so that
+//! the test compiles whatever the types involved are.
+
+use std::cell::RefCell;
+use std::fmt::{self, Write as _};
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use crate::Failure;
+
+/// The directory the tests are written to, `regressions` unless this
+/// environment variable is set.
+pub const DIRECTORY_VAR: &str = "RUTENSPITZ_REGRESSIONS";
+
+#[derive(Hash)]
+enum Expected {
+    Value {
+        rendering: String,
+        process: Option<&'static str>,
+    },
+    Panic,
+    /// A handle was opened, the operations on it follow.
+    Opened,
+}
+
+/// A step along with the outcomes of the calls it compared, one for a
+/// method, the opening of the handle followed by one per operation for a
+/// block on a handle.
+#[derive(Hash)]
+struct Step {
+    code: String,
+    expected: Vec<Expected>,
+}
+
+#[derive(Hash)]
+struct Recording {
+    name: String,
+    setup: String,
+    steps: Vec<Step>,
+}
+
+thread_local! {
+    static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+}
+
+/// Starts recording a sequence on the thread, dropping the previous one.
+///
+/// The name ends up in that of the test. The setup is the code the test
+/// starts with, which has to bind the tested instance to `v` the way the
+/// sequence built it, including any seed or capacity.
+pub fn start(name: &str, setup: impl Into<String>) {
+    RECORDING.with(|recording| {
+        *recording.borrow_mut() = Some(Recording {
+            name: name.to_owned(),
+            setup: setup.into(),
+            steps: vec![],
+        });
+    });
+}
+
+/// Stops recording on the thread.
+pub fn stop() {
+    RECORDING.with(|recording| recording.borrow_mut().take());
+}
+
+/// Records a step, usually an `Op`, about to be executed. Does nothing
+/// unless a recording is active.
+pub fn push(step: &impl fmt::Display) {
+    RECORDING.with(|recording| {
+        if let Some(recording) = recording.borrow_mut().as_mut() {
+            recording.steps.push(Step {
+                code: step.to_string(),
+                expected: vec![],
+            });
+        }
+    });
+}
+
+fn expect(expected: impl FnOnce() -> Expected) {
+    RECORDING.with(|recording| {
+        if let Some(step) = recording
+            .borrow_mut()
+            .as_mut()
+            .and_then(|recording| recording.steps.last_mut())
+        {
+            step.expected.push(expected());
+        }
+    });
+}
+
+/// Records what the model returned for the last step, processed by the
+/// function of `equal_with`, if any. Called by the generated code.
+#[doc(hidden)]
+pub fn record_value(process: Option<&'static str>, rendering: impl FnOnce() -> String) {
+    expect(|| Expected::Value {
+        rendering: rendering(),
+        process,
+    });
+}
+
+/// Records that the model panicked on the last step. Called by the
+/// generated code.
+#[doc(hidden)]
+pub fn record_panic() {
+    expect(|| Expected::Panic);
+}
+
+/// Records that the last step opened a handle on the model. Called by the
+/// generated code.
+#[doc(hidden)]
+pub fn record_opened() {
+    expect(|| Expected::Opened);
+}
+
+/// Whether an item was yielded, for the tests checking iterators whose
+/// order is unspecified.
+#[must_use]
+#[allow(clippy::needless_pass_by_value)]
+pub fn yielded<T>(item: Option<T>) -> bool {
+    item.is_some()
+}
+
+/// Collects the items and sorts them, for the tests checking iterators
+/// whose order is unspecified.
+#[must_use]
+pub fn sorted<T: Ord>(items: impl IntoIterator<Item = T>) -> Vec<T> {
+    let mut items: Vec<_> = items.into_iter().collect();
+    items.sort();
+    items
+}
+
+/// The statement checking the outcome of a single call. Other statements,
+/// and those whose outcome is unknown, are kept as they are.
+fn check(statement: &str, expected: Option<&Expected>) -> String {
+    let call = match statement.strip_suffix(';') {
+        Some(call) if !call.contains('\n') && !call.starts_with("drop(") => call,
+        _ => return statement.to_owned(),
+    };
+    match expected {
+        Some(Expected::Value { rendering, process }) => {
+            if rendering == "()" {
+                return statement.to_owned();
+            }
+            let call = match process {
+                Some(process) => format!("{process}({call})"),
+                None => call.to_owned(),
+            };
+            format!("assert_eq!(format!(\"{{:?}}\", {call}), {rendering:?});")
+        }
+        Some(Expected::Panic) => panics(call),
+        Some(Expected::Opened) | None => statement.to_owned(),
+    }
+}
+
+/// The statement checking that the expression panics.
+fn panics(expression: &str) -> String {
+    format!("assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {expression})).is_err());")
+}
+
+impl Step {
+    /// The step as statements checking its outcomes. In the block of a
+    /// handle, the line opening it is followed by one line per operation.
+    fn check(&self) -> String {
+        let mut expected = self.expected.iter();
+        if !self.code.starts_with("{\n") {
+            return check(&self.code, expected.next());
+        }
+        match expected.next() {
+            Some(Expected::Opened) => {}
+            Some(Expected::Panic) => return panics(&self.code),
+            _ => return self.code.clone(),
+        }
+        let mut lines = self.code.lines();
+        let opening: Vec<_> = lines.by_ref().take(2).collect();
+        let mut block = opening.join("\n");
+        for line in lines {
+            let statement = line.trim_start();
+            let indent = &line[..line.len() - statement.len()];
+            let _ = write!(block, "\n{indent}{}", check(statement, expected.next()));
+        }
+        block
+    }
+}
+
+impl Recording {
+    fn test_name(&self) -> String {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.hash(&mut hasher);
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("regression_{name}_{:016x}", hasher.finish())
+    }
+
+    fn render(&self, failure: &Failure) -> String {
+        let mut test = format!("#[test]\nfn {}() {{\n", self.test_name());
+        let statements =
+            std::iter::once(self.setup.clone()).chain(self.steps.iter().map(Step::check));
+        for statement in statements {
+            for line in statement.lines() {
+                let _ = writeln!(test, "    {line}");
+            }
+        }
+        for line in failure.to_string().lines() {
+            let _ = writeln!(test, "    // {line}");
+        }
+        test.push_str("}\n");
+        test
+    }
+}
+
+/// Renders the recorded sequence as a test, with the failure in a comment
+/// at the end, if a recording is active.
+#[must_use]
+pub fn render(failure: &Failure) -> Option<String> {
+    RECORDING.with(|recording| {
+        recording
+            .borrow()
+            .as_ref()
+            .map(|recording| recording.render(failure))
+    })
+}
+
+/// Writes the recorded sequence out as a test to a file of its own, named
+/// after it, if a recording is active. Returns the path of the file.
+#[must_use]
+pub fn write(failure: &Failure) -> Option<std::io::Result<PathBuf>> {
+    let (name, test) = RECORDING.with(|recording| {
+        let recording = recording.borrow();
+        let recording = recording.as_ref()?;
+        Some((recording.test_name(), recording.render(failure)))
+    })?;
+
+    let directory =
+        PathBuf::from(std::env::var_os(DIRECTORY_VAR).unwrap_or_else(|| "regressions".into()));
+    let path = directory.join(format!("{name}.rs"));
+    Some(
+        std::fs::create_dir_all(&directory)
+            .and_then(|()| std::fs::write(&path, test))
+            .map(|()| path),
+    )
+}
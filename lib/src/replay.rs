@@ -0,0 +1,211 @@
+//! Replaying sequences of operations with a report of what they did.
+//!
+//! A replay runs the operations the way the fuzzing loop does, but records
+//! what the model and the tested instance returned for every call compared
+//! along the way, and stops at the first failure instead of aborting. The
+//! [`Report`] prints as the numbered trace with the results side by side,
+//! followed by the failure, which takes the debugger out of reproducing a
+//! crash.
+//!
+//! The `op` module generated for every specification has a `replay`
+//! function decoding the input the way the fuzzing loop does.
+
+use arbitrary::{Arbitrary, Unstructured};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::elements;
+use crate::failure::{self, Failure};
+use crate::Operation;
+
+/// What the model and the tested instance did on a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Results {
+    /// Both returned, with these `Debug` renderings of the values.
+    Values { model: String, tested: String },
+    /// Either of them panicked.
+    Panics { model: bool, tested: bool },
+}
+
+/// An operation replayed, along with the results of the calls it compared,
+/// more than one for the operations on handles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub op: String,
+    pub results: Vec<Results>,
+}
+
+/// How a replay ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    Failed(Failure),
+    /// A panic not raised as a failure, with its message.
+    Panicked(String),
+}
+
+/// The operations replayed, up to the failing one, and how the replay ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub entries: Vec<Entry>,
+    pub verdict: Verdict,
+}
+
+impl Report {
+    /// The index of the operation that failed, if known.
+    #[must_use]
+    pub fn failed_op(&self) -> Option<usize> {
+        match self.verdict {
+            Verdict::Passed => None,
+            Verdict::Failed(ref failure) => failure.op_index(),
+            Verdict::Panicked(_) => self.entries.len().checked_sub(1),
+        }
+    }
+}
+
+impl fmt::Display for Results {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let panicked = |panicked: bool| if panicked { "panicked" } else { "returned" };
+        match self {
+            Self::Values { model, tested } => {
+                let sign = if model == tested { "==" } else { "!=" };
+                write!(f, "model: {model} {sign} tested: {tested}")
+            }
+            Self::Panics { model, tested } => {
+                write!(f, "model: {} | tested: {}", panicked(*model), panicked(*tested))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, entry) in self.entries.iter().enumerate() {
+            let mut lines = entry.op.lines();
+            writeln!(f, "{index:>5}  {}", lines.next().unwrap_or_default())?;
+            for line in lines {
+                writeln!(f, "       {line}")?;
+            }
+            for results in &entry.results {
+                writeln!(f, "         => {results}")?;
+            }
+        }
+        match self.verdict {
+            Verdict::Passed => writeln!(f, "All {} operations passed.", self.entries.len()),
+            Verdict::Failed(ref failure) => writeln!(f, "{failure}"),
+            Verdict::Panicked(ref message) => writeln!(
+                f,
+                "Operation #{} panicked: {message}",
+                self.entries.len().saturating_sub(1)
+            ),
+        }
+    }
+}
+
+thread_local! {
+    static ENTRIES: RefCell<Option<Vec<Entry>>> = const { RefCell::new(None) };
+}
+
+fn record(results: impl FnOnce() -> Results) {
+    ENTRIES.with(|entries| {
+        if let Some(entry) = entries.borrow_mut().as_mut().and_then(|entries| entries.last_mut()) {
+            entry.results.push(results());
+        }
+    });
+}
+
+/// Records what the model and the tested instance returned on a call, if a
+/// replay is active. Called by the generated code.
+#[doc(hidden)]
+pub fn record_values(model: impl FnOnce() -> String, tested: impl FnOnce() -> String) {
+    record(|| Results::Values {
+        model: model(),
+        tested: tested(),
+    });
+}
+
+/// Records which of the model and the tested instance panicked on a call,
+/// if a replay is active. Called by the generated code.
+#[doc(hidden)]
+pub fn record_panics(model: bool, tested: bool) {
+    record(|| Results::Panics { model, tested });
+}
+
+/// Replays the operations on the model and the tested instance, up to the
+/// first failure.
+///
+/// The panics are silenced meanwhile, on all the threads since the panic
+/// hook is global to the process, and no regression test is written.
+pub fn replay<M, T, O>(ops: impl IntoIterator<Item = O>, mut model: M, mut tested: T) -> Report
+where
+    O: Operation<M, T>,
+{
+    failure::silently(|| {
+        failure::reset_op_index();
+        ENTRIES.with(|entries| *entries.borrow_mut() = Some(vec![]));
+
+        let mut verdict = Verdict::Passed;
+        for op in ops {
+            ENTRIES.with(|entries| {
+                if let Some(entries) = entries.borrow_mut().as_mut() {
+                    entries.push(Entry {
+                        op: op.to_string(),
+                        results: vec![],
+                    });
+                }
+            });
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                op.execute_and_compare(&mut model, &mut tested);
+            }));
+            if let Err(payload) = result {
+                verdict = match payload.downcast::<Failure>() {
+                    Ok(failure) => Verdict::Failed(*failure),
+                    Err(payload) => Verdict::Panicked(failure::panic_message(&*payload)),
+                };
+                break;
+            }
+        }
+        if verdict == Verdict::Passed {
+            drop((model, tested));
+            if let Err(payload) = catch_unwind(elements::check_drops) {
+                if let Ok(failure) = payload.downcast::<Failure>() {
+                    verdict = Verdict::Failed(*failure);
+                }
+            }
+        }
+
+        let entries = ENTRIES.with(|entries| entries.borrow_mut().take());
+        Report {
+            entries: entries.unwrap_or_default(),
+            verdict,
+        }
+    })
+}
+
+/// Replays an input, such as a crash file, see [`replay`]. `new` builds the
+/// model and the tested instance from the start of the input, the way the
+/// fuzzing loop does, and the operations are decoded from the rest. Like in
+/// the fuzzing loop, the operations decoded before one that cannot be are
+/// replayed.
+///
+/// # Errors
+///
+/// Fails if `new` does.
+pub fn replay_input<'a, M, T, O, F>(data: &'a [u8], new: F) -> arbitrary::Result<Report>
+where
+    O: Operation<M, T> + Arbitrary<'a>,
+    F: FnOnce(&mut Unstructured<'a>) -> arbitrary::Result<(M, T)>,
+{
+    let mut ring = Unstructured::new(data);
+    let (model, tested) = new(&mut ring)?;
+    let mut ops: Vec<O> = vec![];
+    while !ring.is_empty() {
+        match O::arbitrary(&mut ring) {
+            Ok(op) => ops.push(op),
+            Err(_) => break,
+        }
+    }
+    Ok(replay(ops, model, tested))
+}
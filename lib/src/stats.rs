@@ -0,0 +1,263 @@
+//! Statistics of a fuzzing campaign, with the `stats` feature.
+//!
+//! The generated code counts how many times every operation executed, how
+//! many times the model and the tested instance panicked on it, and whether
+//! the values the model returned were `Some` or `None`, `Ok` or `Err`, which
+//! tells whether a long campaign ever reached the interesting cases, and
+//! with the `allocations` feature, the allocations of the tested instance.
+//! The lengths of the sequences are counted at the start of the next one.
+//!
+//! [`snapshot`] returns the counts of all the threads so far. If
+//! [`FILE_VAR`] is set, they are also written to that file at the start of a
+//! sequence every [`DUMP_INTERVAL`].
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The file the statistics are written to periodically, if set.
+pub const FILE_VAR: &str = "RUTENSPITZ_STATS";
+
+/// How often the statistics are written to the file.
+pub const DUMP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The counts of an operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub executions: u64,
+    pub model_panics: u64,
+    pub tested_panics: u64,
+    pub some: u64,
+    pub none: u64,
+    pub ok: u64,
+    pub err: u64,
+    /// The allocations of the tested instance, with the `allocations`
+    /// feature.
+    pub allocations: u64,
+    pub deallocations: u64,
+    pub allocated_bytes: u64,
+}
+
+/// The counts of a campaign.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The counts of every operation executed, by name.
+    pub ops: BTreeMap<&'static str, OpStats>,
+    /// How many sequences had every length.
+    pub lengths: BTreeMap<usize, u64>,
+}
+
+impl Stats {
+    /// How many sequences were counted.
+    #[must_use]
+    pub fn sequences(&self) -> u64 {
+        self.lengths.values().sum()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sequences = self.sequences();
+        let total: u64 = self.lengths.iter().map(|(&length, &count)| length as u64 * count).sum();
+        let longest = self.lengths.keys().next_back().copied().unwrap_or_default();
+        writeln!(f, "Sequences: {sequences}, {total} operations, the longest of {longest}")?;
+
+        // The lengths are grouped by powers of two.
+        let mut buckets: BTreeMap<usize, u64> = BTreeMap::new();
+        for (&length, &count) in &self.lengths {
+            *buckets.entry(length.checked_ilog2().map_or(0, |log| log as usize + 1)).or_default() += count;
+        }
+        for (bucket, count) in buckets {
+            let range = match bucket {
+                0 => "0".to_owned(),
+                1 => "1".to_owned(),
+                _ => format!("{}..{}", 1_usize << (bucket - 1), (1_usize << bucket) - 1),
+            };
+            writeln!(f, "    {range:>11}: {count}")?;
+        }
+
+        let width = self.ops.keys().map(|name| name.len()).max().unwrap_or_default().max(9);
+        let allocations = self.ops.values().any(|op| op.allocations > 0 || op.deallocations > 0);
+        write!(
+            f,
+            "{:width$}  {:>10}  {:>12}  {:>13}  {:>10}  {:>10}  {:>10}  {:>10}",
+            "Operation", "executions", "model panics", "tested panics", "Some", "None", "Ok", "Err",
+        )?;
+        if allocations {
+            write!(f, "  {:>11}  {:>13}  {:>15}", "allocations", "deallocations", "allocated bytes")?;
+        }
+        writeln!(f)?;
+        for (name, op) in &self.ops {
+            write!(
+                f,
+                "{name:width$}  {:>10}  {:>12}  {:>13}  {:>10}  {:>10}  {:>10}  {:>10}",
+                op.executions, op.model_panics, op.tested_panics, op.some, op.none, op.ok, op.err,
+            )?;
+            if allocations {
+                write!(f, "  {:>11}  {:>13}  {:>15}", op.allocations, op.deallocations, op.allocated_bytes)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+struct State {
+    stats: Stats,
+    last_dump: Option<Instant>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    stats: Stats {
+        ops: BTreeMap::new(),
+        lengths: BTreeMap::new(),
+    },
+    last_dump: None,
+});
+
+thread_local! {
+    // The length of the sequence being executed on the thread, if any.
+    static LENGTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn update<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    f(&mut STATE.lock().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// The counts so far.
+#[must_use]
+pub fn snapshot() -> Stats {
+    update(|state| state.stats.clone())
+}
+
+/// Starts counting from zero again.
+pub fn reset() {
+    update(|state| state.stats = Stats::default());
+    LENGTH.with(|length| length.set(None));
+}
+
+/// Writes the counts so far to a file.
+///
+/// # Errors
+///
+/// Fails if the file cannot be written.
+pub fn dump(path: &Path) -> std::io::Result<()> {
+    std::fs::write(path, snapshot().to_string())
+}
+
+/// Counts the length of the sequence executed on the thread, if any, and
+/// starts another one. Called when the numbering of the operations is
+/// reset.
+pub(crate) fn start_sequence() {
+    let previous = LENGTH.with(|length| length.replace(Some(0)));
+    let path = std::env::var_os(FILE_VAR);
+    let text = update(|state| {
+        if let Some(previous) = previous {
+            *state.stats.lengths.entry(previous).or_default() += 1;
+        }
+        path.as_ref()?;
+        let now = Instant::now();
+        if state.last_dump.is_some_and(|last_dump| now - last_dump < DUMP_INTERVAL) {
+            return None;
+        }
+        state.last_dump = Some(now);
+        Some(state.stats.to_string())
+    });
+    if let (Some(path), Some(text)) = (path, text) {
+        if let Err(error) = std::fs::write(&path, text) {
+            eprintln!("The statistics could not be written to {}: {error}", Path::new(&path).display());
+        }
+    }
+}
+
+fn update_op(name: &'static str, f: impl FnOnce(&mut OpStats)) {
+    update(|state| f(state.stats.ops.entry(name).or_default()));
+}
+
+/// Counts an execution of an operation. Called by the generated code.
+#[doc(hidden)]
+pub fn record_op(name: &'static str) {
+    LENGTH.with(|length| length.set(length.get().map(|length| length + 1)));
+    update_op(name, |op| op.executions += 1);
+}
+
+/// Counts the panics of a call. Called by the generated code.
+#[doc(hidden)]
+pub fn record_panics(name: &'static str, model: bool, tested: bool) {
+    update_op(name, |op| {
+        op.model_panics += u64::from(model);
+        op.tested_panics += u64::from(tested);
+    });
+}
+
+/// Counts the allocations of the tested instance by an operation. Called by
+/// the generated code.
+#[cfg(feature = "allocations")]
+#[doc(hidden)]
+pub fn record_allocations(name: &'static str, counts: crate::allocations::Counts) {
+    update_op(name, |op| {
+        op.allocations += counts.allocations;
+        op.deallocations += counts.deallocations;
+        op.allocated_bytes += counts.allocated_bytes;
+    });
+}
+
+/// The shape of a value returned.
+#[doc(hidden)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Some,
+    None,
+    Ok,
+    Err,
+}
+
+/// Counts the shape of the value the model returned on a call, if it has
+/// one. Called by the generated code.
+#[doc(hidden)]
+pub fn record_shape(name: &'static str, shape: Option<Shape>) {
+    let Some(shape) = shape else {
+        return;
+    };
+    update_op(name, |op| match shape {
+        Shape::Some => op.some += 1,
+        Shape::None => op.none += 1,
+        Shape::Ok => op.ok += 1,
+        Shape::Err => op.err += 1,
+    });
+}
+
+/// Wraps a value returned, to tell its shape. `(&Probe(&value)).shape()`
+/// picks [`ShapeOf`] for options and results, and [`NoShape`] for anything
+/// else.
+#[doc(hidden)]
+pub struct Probe<'a, T>(pub &'a T);
+
+#[doc(hidden)]
+pub trait ShapeOf {
+    fn shape(&self) -> Option<Shape>;
+}
+
+impl<T> ShapeOf for Probe<'_, Option<T>> {
+    fn shape(&self) -> Option<Shape> {
+        Some(if self.0.is_some() { Shape::Some } else { Shape::None })
+    }
+}
+
+impl<T, E> ShapeOf for Probe<'_, Result<T, E>> {
+    fn shape(&self) -> Option<Shape> {
+        Some(if self.0.is_ok() { Shape::Ok } else { Shape::Err })
+    }
+}
+
+#[doc(hidden)]
+pub trait NoShape {
+    fn shape(&self) -> Option<Shape> {
+        None
+    }
+}
+
+impl<T> NoShape for &Probe<'_, T> {}
@@ -0,0 +1,544 @@
+//! Parsing traces back into operations.
+//!
+//! A trace is what the `Display` implementations of the `Op` enums write
+//! out: one statement per operation, calling a method on `v` with the
+//! arguments written with `Debug`, and blocks for the operations on handles.
+//! [`parse`] reads it back into operations, which can then be replayed with
+//! `execute_and_compare`.
+//!
+//! [`FromTrace`] is implemented for the `Op` enum of every specification
+//! whose argument types all implement it.
+
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::str::FromStr;
+
+use crate::closures::{Closure, Comparator, Mapper, Mutator, Predicate, PROJECT};
+
+/// An error parsing a trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    line: Option<usize>,
+    message: String,
+}
+
+impl Error {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    /// The line of the trace, counting from one, the statement that could
+    /// not be parsed starts on.
+    #[must_use]
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    fn at_line(self, line: usize) -> Self {
+        Self {
+            line: Some(line),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A value that can be read back from the way it is written in a trace,
+/// with `Debug` for the arguments and with `Display` for the operations.
+pub trait FromTrace: Sized {
+    /// # Errors
+    ///
+    /// Fails if the text is not a value of the type.
+    fn from_trace(text: &str) -> Result<Self, Error>;
+}
+
+/// The characters of the text that are not nested in brackets, literals or
+/// the parameters of a closure, along with their offsets. The brackets
+/// opening and closing the outermost groups are included.
+fn top_level(text: &str) -> Vec<(usize, char)> {
+    let mut chars = vec![];
+    let mut depth = 0_usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut params = false;
+    for (offset, c) in text.char_indices() {
+        if let Some(quote_char) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote_char {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '[' | '{' => {
+                if depth == 0 && !params {
+                    chars.push((offset, c));
+                }
+                depth += 1;
+            }
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 && !params {
+                    chars.push((offset, c));
+                }
+            }
+            '|' if depth == 0 => params = !params,
+            _ if depth == 0 && !params => chars.push((offset, c)),
+            _ => {}
+        }
+    }
+    chars
+}
+
+/// Splits the text at the top-level occurrences of the separator, trimming
+/// the parts. An empty text has no parts.
+pub(crate) fn split(text: &str, separator: char) -> Vec<&str> {
+    let text = text.trim();
+    if text.is_empty() {
+        return vec![];
+    }
+    let mut parts = vec![];
+    let mut start = 0;
+    for (offset, c) in top_level(text) {
+        if c == separator {
+            parts.push(text[start..offset].trim());
+            start = offset + c.len_utf8();
+        }
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+/// The text between the delimiters it is enclosed in, if it is.
+pub(crate) fn enclosed<'t>(text: &'t str, open: &str, close: &str) -> Option<&'t str> {
+    text.trim().strip_prefix(open)?.strip_suffix(close)
+}
+
+fn parse_str<T: FromStr>(text: &str, what: &str) -> Result<T, Error> {
+    text.trim()
+        .parse()
+        .map_err(|_| Error::new(format!("`{}` is not {what}", text.trim())))
+}
+
+macro_rules! impl_from_trace_parse {
+    ($($ty:ty),*) => {
+        $(
+            impl FromTrace for $ty {
+                fn from_trace(text: &str) -> Result<Self, Error> {
+                    parse_str(text, concat!("a `", stringify!($ty), "`"))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_trace_parse!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
+
+/// Reads a literal written with `Debug` between the quotes, undoing the
+/// escapes.
+fn unescape(text: &str, quote: char) -> Result<String, Error> {
+    let invalid = || Error::new(format!("`{text}` is not a valid literal"));
+    let inner = text
+        .trim()
+        .strip_prefix(quote)
+        .and_then(|text| text.strip_suffix(quote))
+        .ok_or_else(invalid)?;
+
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next().ok_or_else(invalid)? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            'u' => {
+                let code: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let code = code.strip_prefix('{').ok_or_else(invalid)?;
+                let code = u32::from_str_radix(code, 16).map_err(|_| invalid())?;
+                value.push(char::from_u32(code).ok_or_else(invalid)?);
+            }
+            escaped => value.push(escaped),
+        }
+    }
+    Ok(value)
+}
+
+impl FromTrace for char {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        let value = unescape(text, '\'')?;
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(c),
+            _ => Err(Error::new(format!("`{text}` is not a `char`"))),
+        }
+    }
+}
+
+impl FromTrace for String {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        unescape(text, '"')
+    }
+}
+
+impl<T: FromTrace> FromTrace for Option<T> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        if text.trim() == "None" {
+            return Ok(None);
+        }
+        let inner = enclosed(text, "Some(", ")")
+            .ok_or_else(|| Error::new(format!("`{}` is not an `Option`", text.trim())))?;
+        T::from_trace(inner).map(Some)
+    }
+}
+
+impl<T: FromTrace> FromTrace for Vec<T> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        let inner = enclosed(text, "[", "]")
+            .ok_or_else(|| Error::new(format!("`{}` is not a sequence", text.trim())))?;
+        split(inner, ',').into_iter().map(T::from_trace).collect()
+    }
+}
+
+impl<T: FromTrace> FromTrace for Box<[T]> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        Vec::from_trace(text).map(Vec::into_boxed_slice)
+    }
+}
+
+impl<T: FromTrace> FromTrace for Box<T> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        T::from_trace(text).map(Box::new)
+    }
+}
+
+impl<A: FromTrace, B: FromTrace> FromTrace for (A, B) {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        let items = enclosed(text, "(", ")").map(|inner| split(inner, ','));
+        match items.as_deref() {
+            Some([a, b]) => Ok((A::from_trace(a)?, B::from_trace(b)?)),
+            _ => Err(Error::new(format!("`{}` is not a pair", text.trim()))),
+        }
+    }
+}
+
+/// Splits a range at its top-level `..`, telling whether it is inclusive.
+fn range_bounds(text: &str) -> Result<(&str, &str, bool), Error> {
+    let text = text.trim();
+    let top_level = top_level(text);
+    let offset = top_level
+        .windows(2)
+        .find(|pair| pair[0].1 == '.' && pair[1].1 == '.')
+        .map(|pair| pair[0].0)
+        .ok_or_else(|| Error::new(format!("`{text}` is not a range")))?;
+    let end = &text[offset + 2..];
+    match end.strip_prefix('=') {
+        Some(end) => Ok((&text[..offset], end, true)),
+        None => Ok((&text[..offset], end, false)),
+    }
+}
+
+impl<T: FromTrace> FromTrace for Range<T> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        match range_bounds(text)? {
+            (start, end, false) => Ok(T::from_trace(start)?..T::from_trace(end)?),
+            _ => Err(Error::new(format!("`{}` is not an exclusive range", text.trim()))),
+        }
+    }
+}
+
+impl<T: FromTrace> FromTrace for RangeInclusive<T> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        match range_bounds(text)? {
+            (start, end, true) => Ok(T::from_trace(start)?..=T::from_trace(end)?),
+            _ => Err(Error::new(format!("`{}` is not an inclusive range", text.trim()))),
+        }
+    }
+}
+
+/// A shape of a closure that can be read back from its body, given the
+/// names of the parameters.
+pub trait ParseShape: Sized {
+    fn parse_body(body: &str, params: &[&str]) -> Option<Self>;
+}
+
+/// The name of the parameter at the index, which the body must refer to.
+fn param<'p>(params: &[&'p str], index: usize) -> Option<&'p str> {
+    params.get(index).copied().filter(|&param| param != "_")
+}
+
+impl ParseShape for Predicate {
+    fn parse_body(body: &str, params: &[&str]) -> Option<Self> {
+        if let Ok(result) = body.parse() {
+            return Some(Self::Constant(result));
+        }
+        let rest = body.strip_prefix(&format!("{PROJECT}(&{})", param(params, 0)?))?;
+        if let Some(threshold) = rest.strip_prefix(" < ") {
+            return Some(Self::LessThan(threshold.parse().ok()?));
+        }
+        let (divisor, remainder) = rest.strip_prefix(" % ")?.split_once(" == ")?;
+        Some(Self::Modulo {
+            divisor: divisor.parse().ok()?,
+            remainder: remainder.parse().ok()?,
+        })
+    }
+}
+
+impl ParseShape for Comparator {
+    fn parse_body(body: &str, params: &[&str]) -> Option<Self> {
+        if body == "std::cmp::Ordering::Equal" {
+            return Some(Self::Equal);
+        }
+        let (a, b) = (param(params, 0)?, param(params, 1)?);
+        if body == format!("{PROJECT}(&{a}).cmp(&{PROJECT}(&{b}))") {
+            return Some(Self::Ascending);
+        }
+        if body == format!("{PROJECT}(&{b}).cmp(&{PROJECT}(&{a}))") {
+            return Some(Self::Descending);
+        }
+        let divisor = body
+            .strip_prefix(&format!("({PROJECT}(&{a}) % "))?
+            .split_once(')')?
+            .0;
+        (body == format!("({PROJECT}(&{a}) % {divisor}).cmp(&({PROJECT}(&{b}) % {divisor}))"))
+            .then(|| divisor.parse().ok().map(Self::ModuloAscending))?
+    }
+}
+
+impl<R: FromTrace> ParseShape for Mapper<R> {
+    fn parse_body(body: &str, _params: &[&str]) -> Option<Self> {
+        // A lookup is a table indexed right away: `[..][..]`.
+        let top_level = top_level(body);
+        let table_end = top_level
+            .windows(2)
+            .find(|pair| pair[0].1 == ']' && pair[1].1 == '[')
+            .map(|pair| pair[0].0);
+        match table_end {
+            Some(offset) => {
+                let mut table = Vec::<R>::from_trace(&body[..=offset]).ok()?.into_iter();
+                Some(Self::Lookup(table.next()?, table.collect()))
+            }
+            None => R::from_trace(body).ok().map(Self::Constant),
+        }
+    }
+}
+
+impl<T: FromTrace> ParseShape for Mutator<T> {
+    fn parse_body(body: &str, params: &[&str]) -> Option<Self> {
+        if body == "{}" {
+            return Some(Self::Keep);
+        }
+        let value = body
+            .strip_prefix('*')?
+            .strip_prefix(param(params, 0)?)?
+            .strip_prefix(" = ")?;
+        T::from_trace(value).ok().map(Self::Set)
+    }
+}
+
+impl<S: ParseShape> FromTrace for Closure<S> {
+    fn from_trace(text: &str) -> Result<Self, Error> {
+        const COUNTING: &str = "{ let calls = std::cell::Cell::new(0); move |";
+        const CHECK: &str = "| { let n = calls.get(); calls.set(n + 1); if n == ";
+
+        let invalid = || Error::new(format!("`{}` is not a generated closure", text.trim()));
+        let text = text.trim();
+        let (params, body, panic_on_call) = if let Some(rest) = text.strip_prefix(COUNTING) {
+            let (params, rest) = rest.split_once(CHECK).ok_or_else(invalid)?;
+            let (call, body) = rest.split_once(" { panic!() } ").ok_or_else(invalid)?;
+            let body = body.strip_suffix(" } }").ok_or_else(invalid)?;
+            (params, body, Some(call.parse().map_err(|_| invalid())?))
+        } else {
+            let (params, body) = text
+                .strip_prefix('|')
+                .and_then(|rest| rest.split_once("| "))
+                .ok_or_else(invalid)?;
+            (params, body, None)
+        };
+
+        let params: Vec<_> = params.split(", ").collect();
+        let shape = S::parse_body(body, &params).ok_or_else(invalid)?;
+        Ok(Self {
+            shape,
+            panic_on_call,
+        })
+    }
+}
+
+/// A method call as written in a trace.
+pub struct Call<'t> {
+    pub name: &'t str,
+    args: Vec<&'t str>,
+}
+
+impl<'t> Call<'t> {
+    /// Parses a statement calling a method on the receiver, including the
+    /// forms written for dropping it and for the methods of a
+    /// `transactional` group.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the statement is not a call on the receiver.
+    pub fn parse(statement: &'t str, receiver: &str) -> Result<Self, Error> {
+        let invalid = || Error::new(format!("`{statement}` is not a call on `{receiver}`"));
+
+        let call = statement.trim().strip_suffix(';').ok_or_else(invalid)?;
+        if let Some((savepoint, call)) = enclosed(call, "savepoints.rollback(", ")")
+            .and_then(|rollback| rollback.split_once(&format!(", |id| {receiver}.")))
+        {
+            let name = call.strip_suffix("(id)").ok_or_else(invalid)?;
+            return Ok(Self {
+                name,
+                args: vec![savepoint],
+            });
+        }
+        let call = enclosed(call, "savepoints.save(", ")")
+            .or_else(|| enclosed(call, "savepoints.commit(|| ", ")"))
+            .unwrap_or(call);
+
+        if call == format!("drop({receiver})") {
+            return Ok(Self {
+                name: "drop",
+                args: vec![],
+            });
+        }
+        let call = call
+            .strip_prefix(receiver)
+            .and_then(|call| call.strip_prefix('.'))
+            .ok_or_else(invalid)?;
+        let (name, args) = call.split_once('(').ok_or_else(invalid)?;
+        let args = args.strip_suffix(')').ok_or_else(invalid)?;
+        Ok(Self {
+            name,
+            args: split(args, ','),
+        })
+    }
+
+    /// Checks the number of arguments.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there are not as many.
+    pub fn expect_args(&self, count: usize) -> Result<(), Error> {
+        if self.args.len() == count {
+            Ok(())
+        } else {
+            Err(Error::new(format!(
+                "`{}` takes {count} arguments, not {}",
+                self.name,
+                self.args.len()
+            )))
+        }
+    }
+
+    /// Parses an argument, passed with the given prefix such as `&`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is no such argument or it cannot be parsed.
+    pub fn arg<T: FromTrace>(&self, index: usize, prefix: &str) -> Result<T, Error> {
+        let arg = self
+            .args
+            .get(index)
+            .ok_or_else(|| Error::new(format!("`{}` lacks argument {index}", self.name)))?;
+        let arg = arg
+            .strip_prefix(prefix)
+            .ok_or_else(|| Error::new(format!("`{arg}` is not passed with `{prefix}`")))?;
+        T::from_trace(arg)
+    }
+
+    /// Parses the argument of a rollback, which picks the savepoint.
+    ///
+    /// # Errors
+    ///
+    /// Fails if there is no such argument or it does not pick a savepoint.
+    pub fn savepoint(&self, index: usize) -> Result<u8, Error> {
+        let arg = self
+            .args
+            .get(index)
+            .ok_or_else(|| Error::new(format!("`{}` lacks argument {index}", self.name)))?;
+        parse_str(arg, "a savepoint")
+    }
+}
+
+/// Splits the block written for an operation on a handle into the statement
+/// opening the handle and those of the operations on it.
+///
+/// # Errors
+///
+/// Fails if the statement is not such a block.
+pub fn handle_block(statement: &str) -> Result<(String, Vec<&str>), Error> {
+    let invalid = || Error::new(format!("`{statement}` is not a block operating on a handle"));
+    let mut lines = enclosed(statement, "{\n", "\n}").ok_or_else(invalid)?.lines();
+    let opening = lines
+        .next()
+        .and_then(|line| line.strip_prefix("    let mut h = "))
+        .ok_or_else(invalid)?;
+    // Iterators are exhausted at the end unless an operation closed them.
+    let ops = lines
+        .map(str::trim)
+        .filter(|line| *line != "h.collect::<Vec<_>>();")
+        .collect();
+    Ok((format!("v.{}", opening.strip_prefix("v.").ok_or_else(invalid)?), ops))
+}
+
+/// Splits a trace into the statements of the operations, along with the
+/// lines they start on. Blank lines and comments are skipped.
+fn statements(trace: &str) -> Vec<(usize, String)> {
+    let mut statements: Vec<(usize, String)> = vec![];
+    let mut lines = trace.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.trim_start().starts_with("//") {
+            continue;
+        }
+        match line {
+            "{" => {
+                let mut block = line.to_owned();
+                for (_, line) in lines.by_ref() {
+                    block.push('\n');
+                    block.push_str(line.trim_end());
+                    if line.trim_end() == "}" {
+                        break;
+                    }
+                }
+                statements.push((index + 1, block));
+            }
+            _ => statements.push((index + 1, line.to_owned())),
+        }
+    }
+    statements
+}
+
+/// Parses a trace into operations.
+///
+/// # Errors
+///
+/// Fails on the first statement that is not an operation, telling its line.
+pub fn parse<O: FromTrace>(trace: &str) -> Result<Vec<O>, Error> {
+    statements(trace)
+        .into_iter()
+        .map(|(line, statement)| O::from_trace(&statement).map_err(|error| error.at_line(line)))
+        .collect()
+}
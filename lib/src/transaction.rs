@@ -0,0 +1,190 @@
+//! The model side of transactional structures.
+//!
+//! The methods declared in a `transactional` group are executed on the model
+//! through [`Savepoints`], which wraps the model and keeps a stack of its
+//! snapshots, one per savepoint, along with the identifiers of the
+//! savepoints the tested instance returned in [`Ids`].
+//!
+//! A rollback picks one of the open savepoints or the last one that was
+//! closed, which the tested instance is expected to refuse by panicking.
+//! The trace keeps the identifiers in an [`Ids`] of its own, named
+//! `savepoints`, which picks them the same way.
+
+use std::ops::{Deref, DerefMut};
+
+/// The savepoint a rollback goes back to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pick {
+    /// The open savepoint of the index, counting from the oldest.
+    Open(usize),
+    /// The last savepoint closed, which is no longer valid.
+    Closed,
+}
+
+/// The identifiers of the open savepoints and of the last one closed.
+#[derive(Clone, Debug)]
+pub struct Ids<I> {
+    open: Vec<I>,
+    closed: Option<I>,
+}
+
+impl<I> Default for Ids<I> {
+    fn default() -> Self {
+        Self {
+            open: vec![],
+            closed: None,
+        }
+    }
+}
+
+impl<I: Clone + PartialEq> Ids<I> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a savepoint with the identifier the tested instance returned
+    /// for it.
+    pub fn save(&mut self, id: I) {
+        self.open.push(id);
+    }
+
+    /// Picks the savepoint a rollback goes back to, by its index taken
+    /// modulo the number of the savepoints to pick from: the open ones and
+    /// the last one closed, unless its identifier was given to an open one
+    /// since. Returns `None` if there are none.
+    #[must_use]
+    pub fn pick(&self, savepoint: u8) -> Option<Pick> {
+        let closed = self.closed.as_ref().filter(|&closed| !self.open.contains(closed));
+        let count = self.open.len() + usize::from(closed.is_some());
+        if count == 0 {
+            return None;
+        }
+        let index = usize::from(savepoint) % count;
+        Some(if index < self.open.len() {
+            Pick::Open(index)
+        } else {
+            Pick::Closed
+        })
+    }
+
+    /// The identifier of the savepoint.
+    #[must_use]
+    pub fn id(&self, pick: Pick) -> Option<&I> {
+        match pick {
+            Pick::Open(index) => self.open.get(index),
+            Pick::Closed => self.closed.as_ref(),
+        }
+    }
+
+    /// Picks a savepoint and rolls the tested instance back to it with `f`,
+    /// closing it along with all the later ones. Returns what `f` did, or
+    /// `None` if there was no savepoint to pick from.
+    pub fn rollback<R>(&mut self, savepoint: u8, f: impl FnOnce(I) -> R) -> Option<R> {
+        let pick = self.pick(savepoint)?;
+        let id = self.id(pick)?.clone();
+        self.close(pick);
+        Some(f(id))
+    }
+
+    /// Commits the tested instance with `f`, closing all the savepoints.
+    pub fn commit<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        self.close_all();
+        f()
+    }
+
+    /// Closes the savepoint and all the later ones.
+    fn close(&mut self, pick: Pick) {
+        if let Pick::Open(index) = pick {
+            self.closed = self.open.drain(index..).next();
+        }
+    }
+
+    fn close_all(&mut self) {
+        if let Some(last) = self.open.pop() {
+            self.closed = Some(last);
+        }
+        self.open.clear();
+    }
+}
+
+/// A model along with the snapshots taken at each open savepoint.
+///
+/// Dereferences to the model, so that the other operations are executed on
+/// it directly. The associated functions are not methods, so that they do
+/// not shadow those of the model.
+#[derive(Clone, Debug, Default)]
+pub struct Savepoints<M, I> {
+    model: M,
+    snapshots: Vec<M>,
+    ids: Ids<I>,
+}
+
+impl<M: Clone, I: Clone + PartialEq> Savepoints<M, I> {
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            snapshots: vec![],
+            ids: Ids::new(),
+        }
+    }
+
+    /// Opens a savepoint with the identifier the tested instance returned
+    /// for it.
+    pub fn save(this: &mut Self, id: I) {
+        this.snapshots.push(this.model.clone());
+        this.ids.save(id);
+    }
+
+    /// Picks the savepoint a rollback goes back to, see [`Ids::pick`], along
+    /// with its identifier to pass to the tested instance.
+    #[must_use]
+    pub fn pick(this: &Self, savepoint: u8) -> Option<(Pick, I)> {
+        let pick = this.ids.pick(savepoint)?;
+        Some((pick, this.ids.id(pick)?.clone()))
+    }
+
+    /// Restores the model to the state at the savepoint and closes it along
+    /// with all the later ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the savepoint was closed, as the tested instance should.
+    pub fn rollback(this: &mut Self, pick: Pick) {
+        let Pick::Open(index) = pick else {
+            #[allow(clippy::panic)]
+            {
+                panic!("the savepoint is closed");
+            }
+        };
+        if let Some(model) = this.snapshots.drain(index..).next() {
+            this.model = model;
+        }
+        this.ids.close(pick);
+    }
+
+    /// Closes all the savepoints, keeping the current state.
+    pub fn commit(this: &mut Self) {
+        this.snapshots.clear();
+        this.ids.close_all();
+    }
+
+    /// The number of open savepoints.
+    pub fn depth(this: &Self) -> usize {
+        this.snapshots.len()
+    }
+}
+
+impl<M, I> Deref for Savepoints<M, I> {
+    type Target = M;
+
+    fn deref(&self) -> &M {
+        &self.model
+    }
+}
+
+impl<M, I> DerefMut for Savepoints<M, I> {
+    fn deref_mut(&mut self) -> &mut M {
+        &mut self.model
+    }
+}
@@ -0,0 +1,94 @@
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::check::{check, Config};
+use rutenspitz::replay::Verdict;
+
+use std::collections::BTreeSet;
+
+// A set that forgets about its values past the tenth when it is buggy.
+struct SmallSet {
+    items: Vec<u8>,
+    buggy: bool,
+}
+
+// Taking the values by reference, like the model.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+impl SmallSet {
+    fn insert(&mut self, value: u8) -> bool {
+        if self.items.contains(&value) {
+            return false;
+        }
+        if !self.buggy || self.items.len() < 10 {
+            self.items.push(value);
+        }
+        true
+    }
+
+    fn remove(&mut self, value: &u8) -> bool {
+        let len = self.items.len();
+        self.items.retain(|item| item != value);
+        self.items.len() < len
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = BTreeSet<u8>,
+    tested = SmallSet,
+
+    methods {
+        equal {
+            fn insert(&mut self, value: u8) -> bool;
+            fn remove(&mut self, value: &u8) -> bool;
+            fn len(&self) -> usize;
+        }
+    }
+}
+
+fn run(config: Config, buggy: bool) -> Result<(), rutenspitz::check::Failed> {
+    check::<op::Op>(config).run(|_| {
+        Ok((
+            BTreeSet::new(),
+            SmallSet {
+                items: vec![],
+                buggy,
+            },
+        ))
+    })
+}
+
+#[test]
+fn sound_structures_pass() {
+    run(Config::default(), false).unwrap();
+}
+
+#[test]
+fn failures_are_reproduced_from_their_seed() {
+    let config = Config {
+        cases: 1000,
+        max_ops: 32,
+        seed: 42,
+    };
+    let failed = run(config, true).unwrap_err();
+    // The first cases pass, the bug takes a few values to show.
+    assert!(failed.seed > config.seed, "{failed}");
+    assert!(matches!(failed.report.verdict, Verdict::Failed(_)), "{failed}");
+    assert!(failed.to_string().contains(&format!("seed: {}", failed.seed)));
+
+    let once = Config {
+        cases: 1,
+        seed: failed.seed,
+        ..config
+    };
+    assert_eq!(run(once, true), Err(failed.clone()));
+    // The same seed still passes on a sound structure.
+    assert_eq!(run(once, false), Ok(()));
+    // And the cases before the one failing passed.
+    let before = Config {
+        cases: u32::try_from(failed.seed - config.seed).unwrap(),
+        ..config
+    };
+    assert_eq!(run(before, true), Ok(()));
+}
@@ -0,0 +1,79 @@
+use arbitrary::{Arbitrary, Unstructured};
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::elements::Panicky;
+use rutenspitz::replay::{replay, Verdict};
+
+use std::collections::BTreeSet;
+
+struct SortedSet<T> {
+    items: Vec<T>,
+}
+
+impl<T: Ord> SortedSet<T> {
+    fn insert(&mut self, value: T) -> bool {
+        match self.items.binary_search(&value) {
+            Ok(_) => false,
+            Err(index) => {
+                self.items.insert(index, value);
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, value: &T) -> bool {
+        match self.items.binary_search(value) {
+            Ok(index) => {
+                self.items.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn contains(&self, value: &T) -> bool {
+        self.items.binary_search(value).is_ok()
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = BTreeSet<T>,
+    tested = SortedSet<T>,
+    type_parameters = <T: Clone + std::fmt::Debug + Ord>,
+    methods {
+        equal {
+            fn insert(&mut self, value: T) -> bool;
+            fn remove(&mut self, value: &T) -> bool;
+            fn contains(&self, value: &T) -> bool;
+            fn len(&self) -> usize;
+        }
+    }
+}
+
+type Op = op::Op<Panicky<u8>>;
+
+// Cloning the operations, as the runners and the minimizer do, must not count
+// as a call of the elements and make them panic outside of the comparison.
+#[test]
+fn cloned_operations_replay_alike() {
+    // Small bytes, so that the elements panic on one of their first calls.
+    let data: Vec<u8> = (0..=u8::MAX).cycle().take(4096).map(|i| i.wrapping_mul(167).rotate_left(3) % 4).collect();
+    for chunk in data.chunks(64) {
+        let mut ring = Unstructured::new(chunk);
+        let mut ops: Vec<Op> = vec![];
+        while let (false, Ok(op)) = (ring.is_empty(), Op::arbitrary(&mut ring)) {
+            ops.push(op);
+        }
+        let mut reports = vec![];
+        for _ in 0..4 {
+            reports.push(replay(ops.clone(), BTreeSet::new(), SortedSet { items: vec![] }));
+        }
+        for report in &reports {
+            assert_eq!(report.verdict, Verdict::Passed, "{ops:?}");
+            assert_eq!(report, &reports[0]);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::fork::{Pairs, MAX_PAIRS};
+use rutenspitz::hook::Action;
+use rutenspitz::Failure;
+
+use std::cell::RefCell;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+// A stack whose clones share their items when it is shallow.
+struct Stack {
+    items: Rc<RefCell<Vec<u8>>>,
+    shallow: bool,
+}
+
+impl Stack {
+    fn new(shallow: bool) -> Self {
+        Self {
+            items: Rc::default(),
+            shallow,
+        }
+    }
+
+    fn push(&self, value: u8) {
+        self.items.borrow_mut().push(value);
+    }
+
+    fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+}
+
+impl Clone for Stack {
+    fn clone(&self) -> Self {
+        let items = if self.shallow {
+            Rc::clone(&self.items)
+        } else {
+            Rc::new(RefCell::new(self.items.borrow().clone()))
+        };
+        Self {
+            items,
+            shallow: self.shallow,
+        }
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = Vec<u8>,
+    tested = Stack,
+    fork = true,
+
+    methods {
+        equal {
+            fn push(&mut self, value: u8);
+            fn len(&self) -> usize;
+        }
+    }
+}
+
+use op::{Op, Step};
+
+// Pushes on a fork of the first pair, then checks the length of the first.
+fn steps() -> Vec<Step> {
+    vec![
+        Step::Op {
+            pair: 0,
+            op: Op::push { value: 1 },
+        },
+        Step::Fork { from: 0, to: 0 },
+        Step::Op {
+            pair: 1,
+            op: Op::push { value: 2 },
+        },
+        Step::Op { pair: 0, op: Op::len },
+    ]
+}
+
+fn run(steps: Vec<Step>, tested: Stack) -> Result<(), Failure> {
+    rutenspitz::hook().on_failure(Action::Continue).install();
+    rutenspitz::failure::reset_op_index();
+    let mut pairs = Pairs::new(vec![], tested);
+    catch_unwind(AssertUnwindSafe(|| {
+        for step in steps {
+            pairs.execute_and_compare(step);
+        }
+    }))
+    .map_err(|payload| *payload.downcast::<Failure>().expect("a failure"))
+}
+
+#[test]
+fn forks_are_independent() {
+    assert_eq!(run(steps(), Stack::new(false)), Ok(()));
+}
+
+#[test]
+fn mutations_leaking_into_a_fork_are_caught() {
+    assert_eq!(
+        run(steps(), Stack::new(true)),
+        Err(Failure::ReturnMismatch {
+            op_index: 2,
+            op_name: "len",
+            model: "1".to_owned(),
+            tested: "2".to_owned(),
+        })
+    );
+}
+
+#[test]
+fn steps_are_resolved_against_the_live_pairs() {
+    let mut pairs = Pairs::new(vec![], Stack::new(false));
+    pairs.execute_and_compare(Step::Fork { from: 7, to: 0 });
+
+    let fork = pairs.resolve(Step::Fork { from: 3, to: 0 });
+    assert_eq!(fork, Step::Fork { from: 1, to: 2 });
+    assert_eq!(fork.to_string(), "let mut v2 = v1.clone();");
+
+    let push = pairs.resolve(Step::Op {
+        pair: 5,
+        op: Op::push { value: 3 },
+    });
+    assert_eq!(push.to_string(), "{\n    let v = &mut v1;\n    v.push(3);\n}");
+
+    // Once there are as many pairs as can be, forking replaces the last one.
+    for _ in 0..MAX_PAIRS {
+        pairs.execute_and_compare(Step::Fork { from: 0, to: 0 });
+    }
+    let to = u8::try_from(MAX_PAIRS - 1).unwrap();
+    assert_eq!(pairs.resolve(Step::Fork { from: 0, to: 0 }), Step::Fork { from: 0, to });
+}
@@ -0,0 +1,171 @@
+use arbitrary::{Arbitrary, Unstructured};
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::replay::{replay, Results, Verdict};
+use rutenspitz::Failure;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bug {
+    // Writes through the handle are lost.
+    LostWrite,
+    // The iterators skip the bottom of the stack.
+    SkippedItem,
+}
+
+// The model and the tested instance alike, but for the bug of the latter.
+struct Stack {
+    items: Vec<u8>,
+    bug: Option<Bug>,
+}
+
+struct Top<'s> {
+    items: &'s mut Vec<u8>,
+    lost_write: bool,
+}
+
+impl Top<'_> {
+    fn get(&self) -> Option<&u8> {
+        self.items.last()
+    }
+
+    fn set(&mut self, value: u8) {
+        if let (Some(top), false) = (self.items.last_mut(), self.lost_write) {
+            *top = value;
+        }
+    }
+
+    fn pop(self) -> Option<u8> {
+        self.items.pop()
+    }
+}
+
+impl Stack {
+    fn new(bug: Option<Bug>) -> Self {
+        Self { items: vec![], bug }
+    }
+
+    fn push(&mut self, value: u8) {
+        self.items.push(value);
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    fn top(&mut self) -> Top<'_> {
+        Top {
+            items: &mut self.items,
+            lost_write: self.bug == Some(Bug::LostWrite),
+        }
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &u8> + ExactSizeIterator {
+        self.items.iter().skip(usize::from(self.bug == Some(Bug::SkippedItem)))
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = Stack,
+    tested = Stack,
+
+    methods {
+        equal {
+            fn push(&mut self, value: u8);
+            fn len(&self) -> usize;
+        }
+
+        iterate(next, next_back, nth, size_hint, len) {
+            fn iter(&self) -> impl Iterator<Item = &u8>;
+        }
+
+        handle {
+            fn top(&mut self) -> Top {
+                equal {
+                    fn get(&self) -> Option<&u8>;
+                    fn set(&mut self, value: u8);
+                    fn pop(self) -> Option<u8>;
+                }
+            }
+        }
+    }
+}
+
+use op::{iter, top, Op};
+
+fn mismatch(op_index: usize, op_name: &'static str, model: &str, tested: &str) -> Verdict {
+    Verdict::Failed(Failure::ReturnMismatch {
+        op_index,
+        op_name,
+        model: model.to_owned(),
+        tested: tested.to_owned(),
+    })
+}
+
+#[test]
+fn sound_handles_and_iterators_pass() {
+    let data: Vec<u8> = (0..=u8::MAX).cycle().take(8192).map(|i| i.wrapping_mul(167).rotate_left(3)).collect();
+    let mut blocks = 0;
+    for chunk in data.chunks(128) {
+        let mut ring = Unstructured::new(chunk);
+        let mut ops = vec![];
+        while let (false, Ok(op)) = (ring.is_empty(), Op::arbitrary(&mut ring)) {
+            ops.push(op);
+        }
+        let report = replay(ops, Stack::new(None), Stack::new(None));
+        assert_eq!(report.verdict, Verdict::Passed, "{report}");
+        blocks += report.entries.iter().filter(|entry| entry.op.starts_with('{')).count();
+    }
+    assert!(blocks > 0);
+}
+
+#[test]
+fn operations_on_handles_are_compared() {
+    let ops = vec![
+        Op::push { value: 1 },
+        Op::top {
+            ops: vec![top::Op::set { value: 2 }, top::Op::get],
+        },
+    ];
+    let report = replay(ops, Stack::new(None), Stack::new(Some(Bug::LostWrite)));
+    assert_eq!(report.verdict, mismatch(1, "top", "Some(2)", "Some(1)"));
+    assert_eq!(report.entries[1].op, "{\n    let mut h = v.top();\n    h.set(2);\n    h.get();\n}");
+    assert_eq!(report.entries[1].results.len(), 2);
+}
+
+#[test]
+fn operations_after_closing_a_handle_are_dropped() {
+    let ops = vec![
+        Op::push { value: 1 },
+        Op::top {
+            ops: vec![top::Op::pop, top::Op::get],
+        },
+        Op::len,
+    ];
+    let report = replay(ops, Stack::new(None), Stack::new(None));
+    assert_eq!(report.verdict, Verdict::Passed);
+    assert_eq!(report.entries[1].op, "{\n    let mut h = v.top();\n    h.pop();\n}");
+    assert_eq!(
+        report.entries[1].results,
+        [Results::Values {
+            model: "Some(1)".to_owned(),
+            tested: "Some(1)".to_owned(),
+        }]
+    );
+}
+
+#[test]
+fn iterators_are_stepped_in_lockstep() {
+    let pushes = || (1..=3).map(|value| Op::push { value });
+
+    let ops = pushes()
+        .chain([Op::iter {
+            ops: vec![iter::Op::next_back, iter::Op::next],
+        }])
+        .collect::<Vec<_>>();
+    let report = replay(ops, Stack::new(None), Stack::new(Some(Bug::SkippedItem)));
+    assert_eq!(report.verdict, mismatch(3, "iter", "Some(1)", "Some(2)"));
+
+    // What the steps leave of the iterators is compared too.
+    let ops = pushes().chain([Op::iter { ops: vec![] }]).collect::<Vec<_>>();
+    let report = replay(ops, Stack::new(None), Stack::new(Some(Bug::SkippedItem)));
+    assert_eq!(report.verdict, mismatch(3, "iter", "[1, 2, 3]", "[2, 3]"));
+}
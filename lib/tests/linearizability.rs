@@ -0,0 +1,99 @@
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::linearizability::History;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+// A set shared between threads, which claims to insert every value when it
+// is forgetful, even those it holds already.
+struct SharedSet {
+    items: Mutex<BTreeSet<u8>>,
+    forgetful: bool,
+}
+
+impl SharedSet {
+    fn new(forgetful: bool) -> Self {
+        Self {
+            items: Mutex::new(BTreeSet::new()),
+            forgetful,
+        }
+    }
+
+    fn insert(&self, value: u8) -> bool {
+        let inserted = self.items.lock().unwrap().insert(value);
+        inserted || self.forgetful
+    }
+
+    // Like the model.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn contains(&self, value: &u8) -> bool {
+        self.items.lock().unwrap().contains(value)
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = BTreeSet<u8>,
+    tested = SharedSet,
+    concurrent = true,
+
+    methods {
+        equal {
+            fn insert(&self, value: u8) -> bool;
+            fn contains(&self, value: &u8) -> bool;
+        }
+    }
+}
+
+use op::Op;
+
+// Inserting a value twice on the same thread returns `true` the second time
+// only if the set is forgetful, whatever the other thread does meanwhile.
+fn threads() -> Vec<Vec<Op>> {
+    vec![
+        vec![
+            Op::insert { value: 1 },
+            Op::contains { value: 1 },
+            Op::insert { value: 1 },
+            Op::insert { value: 4 },
+            Op::contains { value: 4 },
+        ],
+        vec![
+            Op::insert { value: 2 },
+            Op::contains { value: 1 },
+            Op::insert { value: 3 },
+        ],
+    ]
+}
+
+#[test]
+fn sound_histories_are_linearized() {
+    let threads = threads();
+    for _ in 0..32 {
+        let tested = SharedSet::new(false);
+        let history = History::record(&tested, &threads);
+        let order = history.linearize(&BTreeSet::new()).expect("the history is linearizable");
+        let mut calls = order.clone();
+        calls.sort_unstable();
+        assert_eq!(calls, (0..8).collect::<Vec<_>>(), "{history}");
+        assert!(history.minimal_failing_prefix(&BTreeSet::new()).is_none());
+    }
+}
+
+#[test]
+fn the_failing_prefix_ends_with_the_call_to_blame() {
+    let threads = threads();
+    for _ in 0..32 {
+        let tested = SharedSet::new(true);
+        let history = History::record(&tested, &threads);
+        assert!(history.linearize(&BTreeSet::new()).is_none(), "{history}");
+
+        let prefix = history
+            .minimal_failing_prefix(&BTreeSet::new())
+            .expect("the history is not linearizable");
+        assert!(prefix.linearize(&BTreeSet::new()).is_none());
+        let prefix = prefix.to_string();
+        assert_eq!(prefix.matches("v.insert(1);").count(), 2, "{prefix}");
+        assert!(!prefix.contains("v.insert(4);"), "{prefix}");
+        assert!(!prefix.contains("v.contains(&4);"), "{prefix}");
+    }
+}
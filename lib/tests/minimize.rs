@@ -0,0 +1,117 @@
+use arbitrary::{Arbitrary, Unstructured};
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::minimize::minimize;
+use rutenspitz::replay::{replay, Verdict};
+use rutenspitz::Failure;
+
+use std::collections::BTreeSet;
+use std::mem::Discriminant;
+
+// A sorted set that claims to insert its third value, but loses it.
+#[derive(Default)]
+struct SortedSet {
+    items: Vec<u8>,
+}
+
+// Taking the values by reference, like the model.
+#[allow(clippy::trivially_copy_pass_by_ref)]
+impl SortedSet {
+    fn insert(&mut self, value: u8) -> bool {
+        match self.items.binary_search(&value) {
+            Ok(_) => false,
+            Err(_) if self.items.len() == 2 => true,
+            Err(index) => {
+                self.items.insert(index, value);
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, value: &u8) -> bool {
+        match self.items.binary_search(value) {
+            Ok(index) => {
+                self.items.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn contains(&self, value: &u8) -> bool {
+        self.items.binary_search(value).is_ok()
+    }
+
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+}
+
+arbitrary_stateful_operations! {
+    model = BTreeSet<u8>,
+    tested = SortedSet,
+
+    methods {
+        equal {
+            fn insert(&mut self, value: u8) -> bool;
+            fn remove(&mut self, value: &u8) -> bool;
+            fn contains(&self, value: &u8) -> bool;
+            fn len(&self) -> usize;
+        }
+    }
+}
+
+use op::Op;
+
+fn new() -> (BTreeSet<u8>, SortedSet) {
+    (BTreeSet::new(), SortedSet::default())
+}
+
+// The failure of the sequence, as its kind and the name of the operation.
+fn failure(ops: &[Op]) -> Option<(Discriminant<Failure>, &'static str)> {
+    let (model, tested) = new();
+    match replay(ops.to_vec(), model, tested).verdict {
+        Verdict::Failed(failure) => Some((std::mem::discriminant(&failure), failure.op_name()?)),
+        _ => None,
+    }
+}
+
+#[test]
+fn failing_sequences_are_reduced() {
+    // Pseudorandom bytes, the same on every run.
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let data: Vec<u8> = std::iter::repeat_with(|| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state.to_le_bytes()[0]
+    })
+    .take(4096)
+    .collect();
+    let mut minimized = 0;
+    for chunk in data.chunks(256) {
+        let mut ring = Unstructured::new(chunk);
+        let mut ops = vec![];
+        while let (false, Ok(op)) = (ring.is_empty(), Op::arbitrary(&mut ring)) {
+            ops.push(op);
+        }
+        let Some(original) = failure(&ops) else {
+            continue;
+        };
+
+        let reduced = minimize(ops.clone(), new).expect("the sequence fails");
+        // Three distinct values, the last of which is lost, and the operation
+        // telling.
+        assert_eq!(reduced.len(), 4, "{reduced:?}");
+        assert!(reduced[..3].iter().all(|op| matches!(op, Op::insert { .. })), "{reduced:?}");
+        assert!(reduced.len() < ops.len());
+        assert_eq!(failure(&reduced), Some(original));
+        minimized += 1;
+    }
+    assert!(minimized > 0);
+}
+
+#[test]
+fn passing_sequences_are_left_alone() {
+    let ops = vec![Op::insert { value: 1 }, Op::insert { value: 2 }, Op::len];
+    assert!(minimize(ops, new).is_none());
+}
@@ -0,0 +1,187 @@
+use arbitrary::{Arbitrary, Unstructured};
+use rutenspitz::arbitrary_stateful_operations;
+use rutenspitz::transaction::Savepoints;
+
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::ops::Range;
+
+// Methods taking all kinds of arguments, for their traces to be parsed back.
+#[derive(Clone, Default)]
+struct Log {
+    values: Vec<u8>,
+    name: String,
+}
+
+struct Last<'l> {
+    values: &'l mut Vec<u8>,
+}
+
+impl Last<'_> {
+    fn get(&self) -> Option<&u8> {
+        self.values.last()
+    }
+
+    fn set(&mut self, value: u8) {
+        if let Some(last) = self.values.last_mut() {
+            *last = value;
+        }
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+impl Log {
+    fn push(&mut self, value: u8) {
+        self.values.push(value);
+    }
+
+    fn get(&self, index: Option<u16>) -> Option<&u8> {
+        self.values.get(usize::from(index?))
+    }
+
+    fn rename(&mut self, name: String) {
+        self.name = name;
+    }
+
+    fn extend(&mut self, values: Vec<u8>) {
+        self.values.extend(values);
+    }
+
+    fn push_if(&mut self, (value, push): (u8, bool)) {
+        if push {
+            self.values.push(value);
+        }
+    }
+
+    fn count(&self, range: Range<u8>) -> usize {
+        self.values.iter().filter(|value| range.contains(value)).count()
+    }
+
+    fn retain(&mut self, f: impl FnMut(&u8) -> bool) {
+        self.values.retain(f);
+    }
+
+    fn sort_by(&mut self, f: impl FnMut(&u8, &u8) -> Ordering) {
+        self.values.sort_by(f);
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &u8> {
+        self.values.iter()
+    }
+
+    fn last(&mut self) -> Last<'_> {
+        Last {
+            values: &mut self.values,
+        }
+    }
+
+    fn savepoint(&mut self) -> usize {
+        self.values.len()
+    }
+
+    fn rollback(&mut self, savepoint: usize) {
+        self.values.truncate(savepoint);
+    }
+
+    // There is nothing to forget on commit.
+    #[allow(clippy::unused_self)]
+    fn commit(&mut self) {}
+}
+
+arbitrary_stateful_operations! {
+    model = Savepoints<Log, usize>,
+    tested = Log,
+
+    methods {
+        equal {
+            fn push(&mut self, value: u8);
+            fn get(&self, index: Option<u16>) -> Option<&u8>;
+            fn rename(&mut self, name: String);
+            fn extend(&mut self, values: Vec<u8>);
+            fn push_if(&mut self, pair: (u8, bool));
+            fn count(&self, range: Range<u8>) -> usize;
+            fn retain(&mut self, f: impl FnMut(&u8) -> bool);
+            fn sort_by(&mut self, f: impl FnMut(&u8, &u8) -> Ordering);
+        }
+
+        iterate(next, nth) {
+            fn iter(&self) -> impl Iterator<Item = &u8>;
+        }
+
+        handle {
+            fn last(&mut self) -> Last {
+                equal {
+                    fn get(&self) -> Option<&u8>;
+                    fn set(&mut self, value: u8);
+                    fn drop(self);
+                }
+            }
+        }
+
+        transactional {
+            savepoint fn savepoint(&mut self) -> usize;
+            rollback fn rollback(&mut self, savepoint: usize);
+            commit fn commit(&mut self);
+        }
+    }
+}
+
+use op::Op;
+
+fn render(ops: &[Op]) -> String {
+    ops.iter().fold(String::new(), |mut trace, op| {
+        writeln!(trace, "{op}").unwrap();
+        trace
+    })
+}
+
+#[test]
+fn traces_are_parsed_back() {
+    // Pseudorandom bytes, the same on every run.
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let data: Vec<u8> = std::iter::repeat_with(|| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state.to_le_bytes()[0]
+    })
+    .take(1 << 16)
+    .collect();
+
+    let mut traces = String::new();
+    for chunk in data.chunks(256) {
+        let mut ring = Unstructured::new(chunk);
+        let mut ops = vec![];
+        while let (false, Ok(op)) = (ring.is_empty(), Op::arbitrary(&mut ring)) {
+            ops.push(op);
+        }
+        let trace = render(&ops);
+        // The operations after a handle is closed are not written, so it is
+        // the trace of what was parsed that has to be the same.
+        let parsed = op::parse_trace(&trace).unwrap_or_else(|error| unreachable!("{error}\n{trace}"));
+        assert_eq!(render(&parsed), trace);
+        traces += &trace;
+    }
+
+    // Every kind of argument and statement went through the parser.
+    for statement in [
+        "v.rename(",
+        "v.count(",
+        "rutenspitz::closures::project",
+        "let mut h = v.iter();",
+        "let mut h = v.last();",
+        "savepoints.rollback(",
+        "savepoints.commit(",
+    ] {
+        assert!(traces.contains(statement), "{statement}");
+    }
+}
+
+#[test]
+fn errors_name_their_line() {
+    let trace = "v.push(1);\n\n// A comment.\nv.push(256);\n";
+    let error = op::parse_trace(trace).unwrap_err();
+    assert_eq!(error.line(), Some(4), "{error}");
+
+    assert_eq!(Op::from_trace_line("v.push(3);"), Ok(Op::push { value: 3 }));
+}
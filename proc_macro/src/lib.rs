@@ -6,66 +6,335 @@ use syn::parse_macro_input;
 use syn::spanned::Spanned;
 
 mod kw {
+    syn::custom_keyword!(commit);
+    syn::custom_keyword!(concurrent);
+    syn::custom_keyword!(consistency);
     syn::custom_keyword!(equal);
     syn::custom_keyword!(equal_with);
+    syn::custom_keyword!(fork);
+    syn::custom_keyword!(handle);
+    syn::custom_keyword!(iterate);
+    syn::custom_keyword!(iterate_unordered);
     syn::custom_keyword!(methods);
     syn::custom_keyword!(model);
     syn::custom_keyword!(post);
     syn::custom_keyword!(pre);
+    syn::custom_keyword!(rollback);
+    syn::custom_keyword!(savepoint);
+    syn::custom_keyword!(size);
     syn::custom_keyword!(tested);
+    syn::custom_keyword!(transactional);
     syn::custom_keyword!(type_parameters);
 }
 
+#[derive(Clone, Copy)]
+enum ClosureKind {
+    Comparator,
+    Mapper,
+    Mutator,
+    Predicate,
+}
+
 #[allow(clippy::enum_variant_names)]
 enum PassingMode {
     ByValue,
+    ByValueInto,
     ByRef,
     ByRefMut,
+    // A generated closure with the given number of parameters.
+    ByClosure(ClosureKind, usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Receiver {
+    Ref,
+    RefMut,
+    Value,
 }
 
 struct Argument {
     name: syn::Ident,
     ty: syn::Type,
     passing_mode: PassingMode,
+    // The proptest strategy given with `#[strategy(...)]`, if any.
+    strategy: Option<syn::Expr>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HandleKind {
+    Custom,
+    Iterator { ordered: bool },
+}
+
+struct Handle {
+    kind: HandleKind,
+    methods: Vec<Method>,
+}
+
+// The role of a method in a `transactional` group.
+#[derive(Clone, Copy, PartialEq)]
+enum Transaction {
+    Savepoint,
+    Rollback,
+    Commit,
 }
 
 struct Method {
     name: syn::Ident,
-    // self_mut: bool,
+    receiver: Receiver,
     inputs: Vec<Argument>,
     process_result: Option<syn::Path>,
+    // Operations on the handle returned by the method, if it opens one.
+    handle: Option<Handle>,
+    transaction: Option<Transaction>,
+    // The variant of `rutenspitz::complexity::Bound` given with
+    // `#[complexity(...)]`, if any.
+    complexity: Option<syn::Ident>,
+    // Whether the method must not allocate, given with `#[no_alloc]`.
+    no_alloc: bool,
+    // Whether the method declares a return type.
+    returns: bool,
     // output: syn::Type
 }
 
-impl syn::parse::Parse for Method {
-    fn parse(input: syn::parse::ParseStream<'_>) -> syn::Result<Self> {
-        let method_item: syn::TraitItemFn = input.parse()?;
+/// Reads the attributes of a method: the bound of its cost, given as
+/// `#[complexity(1)]`, `#[complexity(log n)]`, `#[complexity(n)]`,
+/// `#[complexity(n log n)]` or `#[complexity(n^2)]`, and whether it must not
+/// allocate, given as `#[no_alloc]`. The other attributes, such as doc
+/// comments, are ignored.
+fn method_attributes(attrs: &[syn::Attribute]) -> syn::Result<(Option<syn::Ident>, bool)> {
+    let mut complexity = None;
+    let mut no_alloc = false;
+    for attr in attrs {
+        if attr.path().is_ident("no_alloc") {
+            attr.meta.require_path_only()?;
+            no_alloc = true;
+            continue;
+        }
+        if !attr.path().is_ident("complexity") {
+            continue;
+        }
+        if complexity.is_some() {
+            return Err(syn::Error::new(attr.span(), "duplicate `#[complexity(...)]`"));
+        }
+        let bound: pm2::TokenStream = attr.parse_args()?;
+        let variant = match bound.to_string().replace(' ', "").as_str() {
+            "1" => "Constant",
+            "logn" => "Log",
+            "n" => "Linear",
+            "nlogn" => "LinearLog",
+            "n^2" => "Quadratic",
+            _ => {
+                return Err(syn::Error::new(
+                    bound.span(),
+                    "expected one of: 1, log n, n, n log n, n^2",
+                ));
+            }
+        };
+        complexity = Some(syn::Ident::new(variant, attr.span()));
+    }
+    Ok((complexity, no_alloc))
+}
 
-        if let Some(ref defaultness) = method_item.default {
-            return Err(syn::Error::new(defaultness.span(), "unexpected `default`"));
+/// Reads the proptest strategy of an argument, given as
+/// `#[strategy(<expression>)]`, the only attribute arguments can have.
+fn argument_strategy(attrs: &[syn::Attribute]) -> syn::Result<Option<syn::Expr>> {
+    let mut strategy = None;
+    for attr in attrs {
+        if !attr.path().is_ident("strategy") {
+            return Err(syn::Error::new(
+                attr.span(),
+                "unexpected attribute, only `#[strategy(...)]` is allowed on arguments",
+            ));
         }
-        if let Some(ref constness) = method_item.sig.constness {
-            return Err(syn::Error::new(constness.span(), "unexpected `const`"));
+        if strategy.is_some() {
+            return Err(syn::Error::new(attr.span(), "duplicate `#[strategy(...)]`"));
         }
-        if let Some(ref asyncness) = method_item.sig.asyncness {
-            return Err(syn::Error::new(asyncness.span(), "unexpected `async`"));
+        strategy = Some(attr.parse_args()?);
+    }
+    Ok(strategy)
+}
+
+/// Picks the shape of the generated closures for a closure type given as
+/// `impl Fn*(..) -> ..` and the type of the `rutenspitz::closures::Closure`
+/// that represents them.
+fn closure_type(impl_trait: &syn::TypeImplTrait) -> syn::Result<(syn::Type, ClosureKind, usize)> {
+    let segment = impl_trait
+        .bounds
+        .iter()
+        .find_map(|bound| match bound {
+            syn::TypeParamBound::Trait(bound) => bound.path.segments.last(),
+            _ => None,
+        })
+        .filter(|segment| {
+            segment.ident == "Fn" || segment.ident == "FnMut" || segment.ident == "FnOnce"
+        })
+        .ok_or_else(|| syn::Error::new(impl_trait.span(), "expected a closure type"))?;
+    let syn::PathArguments::Parenthesized(ref args) = segment.arguments else {
+        return Err(syn::Error::new(segment.span(), "expected a closure type"));
+    };
+
+    let arity = args.inputs.len();
+    let output = match args.output {
+        syn::ReturnType::Type(_, ref ty) => match **ty {
+            syn::Type::Tuple(ref tuple) if tuple.elems.is_empty() => None,
+            ref ty => Some(ty),
+        },
+        syn::ReturnType::Default => None,
+    };
+    let last_ident = |ty: &syn::Type| match ty {
+        syn::Type::Path(syn::TypePath { path, .. }) => {
+            path.segments.last().map(|segment| segment.ident.to_string())
         }
-        if let Some(ref unsafety) = method_item.sig.unsafety {
-            return Err(syn::Error::new(unsafety.span(), "unexpected `unsafe`"));
+        _ => None,
+    };
+
+    let (shape, kind): (syn::Type, _) = match output {
+        None => match args.inputs.first() {
+            Some(syn::Type::Reference(syn::TypeReference {
+                mutability: Some(_),
+                elem,
+                ..
+            })) => (
+                syn::parse_quote! { rutenspitz::closures::Mutator<#elem> },
+                ClosureKind::Mutator,
+            ),
+            _ => {
+                return Err(syn::Error::new(
+                    args.span(),
+                    "a closure returning nothing must take a `&mut` first argument",
+                ));
+            }
+        },
+        Some(ty) if last_ident(ty).as_deref() == Some("bool") => (
+            syn::parse_quote! { rutenspitz::closures::Predicate },
+            ClosureKind::Predicate,
+        ),
+        Some(ty) if last_ident(ty).as_deref() == Some("Ordering") => {
+            if arity != 2 {
+                return Err(syn::Error::new(
+                    args.span(),
+                    "a comparator must take two arguments",
+                ));
+            }
+            (
+                syn::parse_quote! { rutenspitz::closures::Comparator },
+                ClosureKind::Comparator,
+            )
         }
+        Some(ty) => (
+            syn::parse_quote! { rutenspitz::closures::Mapper<#ty> },
+            ClosureKind::Mapper,
+        ),
+    };
+
+    Ok((
+        syn::parse_quote! { rutenspitz::closures::Closure<#shape> },
+        kind,
+        arity,
+    ))
+}
+
+impl Method {
+    fn closes_handle(&self) -> bool {
+        self.receiver == Receiver::Value
+    }
+
+    fn yields_item(&self) -> bool {
+        self.name == "next" || self.name == "next_back" || self.name == "nth"
+    }
+}
 
-        let (receivers, args) = method_item
-            .sig
+const ITERATOR_STEPS: &[&str] = &["len", "next", "next_back", "nth", "size_hint"];
+
+/// The operations on an iterator handle for the given steps. Dropping the
+/// iterator is always one of them.
+fn iterator_steps(steps: &[syn::Ident], ordered: bool) -> syn::Result<Vec<Method>> {
+    let mut methods = vec![];
+    for step in steps {
+        if !ITERATOR_STEPS.iter().any(|name| step == name) {
+            return Err(syn::Error::new(
+                step.span(),
+                format!("expected one of: {}", ITERATOR_STEPS.join(", ")),
+            ));
+        }
+        if !ordered && step == "nth" {
+            return Err(syn::Error::new(
+                step.span(),
+                "`nth` skips over elements that can't be compared without an order",
+            ));
+        }
+        let mut method: Method = if step == "nth" {
+            syn::parse_quote! { fn nth(&mut self, n: u8); }
+        } else if step == "len" || step == "size_hint" {
+            syn::parse_quote! { fn #step(&self); }
+        } else {
+            syn::parse_quote! { fn #step(&mut self); }
+        };
+        for input in &mut method.inputs {
+            input.passing_mode = PassingMode::ByValueInto;
+        }
+        methods.push(method);
+    }
+    methods.push(syn::parse_quote! { fn drop(self); });
+    Ok(methods)
+}
+
+/// Checks that a method is neither `const`, `async` nor `unsafe`.
+fn check_qualifiers(sig: &syn::Signature) -> syn::Result<()> {
+    if let Some(ref constness) = sig.constness {
+        return Err(syn::Error::new(constness.span(), "unexpected `const`"));
+    }
+    if let Some(ref asyncness) = sig.asyncness {
+        return Err(syn::Error::new(asyncness.span(), "unexpected `async`"));
+    }
+    if let Some(ref unsafety) = sig.unsafety {
+        return Err(syn::Error::new(unsafety.span(), "unexpected `unsafe`"));
+    }
+    Ok(())
+}
+
+impl syn::parse::Parse for Method {
+    fn parse(input: syn::parse::ParseStream<'_>) -> syn::Result<Self> {
+        use syn::{braced, Token};
+
+        let (complexity, no_alloc) = method_attributes(&input.call(syn::Attribute::parse_outer)?)?;
+        let sig: syn::Signature = input.parse()?;
+        check_qualifiers(&sig)?;
+
+        let handle = if input.peek(syn::token::Brace) {
+            let inner;
+            braced!(inner in input);
+            Some(Handle {
+                kind: HandleKind::Custom,
+                methods: parse_method_groups(&inner, true)?,
+            })
+        } else {
+            let _: Token![;] = input.parse()?;
+            None
+        };
+
+        let (receivers, args) = sig
             .inputs
             .iter()
             .map(|input| match input {
-                syn::FnArg::Receiver(receiver) => Either::Left(receiver),
-                syn::FnArg::Typed(syn::PatType { ty, pat, .. }) => {
+                syn::FnArg::Receiver(receiver) => Ok(Either::Left(receiver)),
+                syn::FnArg::Typed(syn::PatType { attrs, ty, pat, .. }) => {
                     let ident = match **pat {
                         syn::Pat::Ident(syn::PatIdent { ref ident, .. }) => ident.clone(),
                         ref pat => syn::Ident::new("_", pat.span()),
                     };
-                    match **ty {
+                    let strategy = argument_strategy(attrs)?;
+                    Ok(match **ty {
+                        syn::Type::ImplTrait(ref impl_trait) => {
+                            let (ty, kind, arity) = closure_type(impl_trait)?;
+                            Either::Right(Argument {
+                                name: ident,
+                                ty,
+                                passing_mode: PassingMode::ByClosure(kind, arity),
+                                strategy,
+                            })
+                        }
                         syn::Type::Reference(syn::TypeReference {
                             ref mutability,
                             ref elem,
@@ -78,40 +347,55 @@ impl syn::parse::Parse for Method {
                             } else {
                                 PassingMode::ByRef
                             },
+                            strategy,
                         }),
                         ref ty => Either::Right(Argument {
                             name: ident,
                             ty: ty.clone(),
                             passing_mode: PassingMode::ByValue,
+                            strategy,
                         }),
-                    }
+                    })
                 }
             })
+            .collect::<syn::Result<Vec<_>>>()?
+            .into_iter()
             .partition::<Vec<_>, _>(Either::is_left);
 
         let receivers: Vec<_> = receivers.into_iter().filter_map(Either::left).collect();
         let args: Vec<_> = args.into_iter().filter_map(Either::right).collect();
 
-        let receiver = receivers.first();
-        if let Some(receiver) = receiver {
-            if receiver.reference.is_none() {
+        let receiver = match receivers.first() {
+            Some(receiver) if receiver.reference.is_none() => Receiver::Value,
+            Some(receiver) if receiver.mutability.is_some() => Receiver::RefMut,
+            Some(_) => Receiver::Ref,
+            None => {
                 return Err(syn::Error::new(
-                    receiver.span(),
-                    "unexpected by-value receiver",
+                    sig.span(),
+                    "unexpected method with no receiver",
+                ));
+            }
+        };
+
+        if handle.is_some() {
+            if let Some(arg) = args.iter().find(|arg| arg.name == "ops") {
+                return Err(syn::Error::new(
+                    arg.name.span(),
+                    "`ops` is reserved for the operations on the handle",
                 ));
             }
-        } else {
-            return Err(syn::Error::new(
-                method_item.span(),
-                "unexpected method with no receiver",
-            ));
         }
 
         Ok(Self {
-            name: method_item.sig.ident,
-            // self_mut: receiver.map_or(false, |r| r.mutability.is_some()),
+            name: sig.ident,
+            receiver,
             process_result: None,
             inputs: args,
+            handle,
+            transaction: None,
+            complexity,
+            no_alloc,
+            returns: !matches!(sig.output, syn::ReturnType::Default),
             /*output: match method_item.sig.output {
                 syn::ReturnType::Default =>
                     syn::parse_str("()").unwrap(),
@@ -122,27 +406,248 @@ impl syn::parse::Parse for Method {
     }
 }
 
+/// Parses the methods of a `transactional` group, each preceded by its role.
+/// The identifier of a savepoint the rollback takes is replaced by the index
+/// of one of the open savepoints.
+fn parse_transactional_methods(input: syn::parse::ParseStream<'_>) -> syn::Result<Vec<Method>> {
+    let mut methods: Vec<Method> = vec![];
+
+    while !input.is_empty() {
+        let lookahead = input.lookahead1();
+        let transaction = if lookahead.peek(kw::savepoint) {
+            let _: kw::savepoint = input.parse()?;
+            Transaction::Savepoint
+        } else if lookahead.peek(kw::rollback) {
+            let _: kw::rollback = input.parse()?;
+            Transaction::Rollback
+        } else if lookahead.peek(kw::commit) {
+            let _: kw::commit = input.parse()?;
+            Transaction::Commit
+        } else {
+            return Err(lookahead.error());
+        };
+
+        let mut method: Method = input.parse()?;
+        if methods.iter().any(|m| m.transaction == Some(transaction)) {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "each role in a `transactional` group can only be given once",
+            ));
+        }
+        if method.handle.is_some() || method.receiver == Receiver::Value {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "transactional methods must take `&self` or `&mut self`",
+            ));
+        }
+        if method.complexity.is_some() || method.no_alloc {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "complexity bounds and `#[no_alloc]` are not supported on transactional methods",
+            ));
+        }
+        // Their outcome is compared with that of the model, which returns
+        // nothing.
+        if transaction != Transaction::Savepoint && method.returns {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "rollback and commit methods must not return a value",
+            ));
+        }
+        let expected_args = usize::from(transaction == Transaction::Rollback);
+        if method.inputs.len() != expected_args {
+            return Err(syn::Error::new(
+                method.name.span(),
+                if expected_args == 0 {
+                    "expected no arguments"
+                } else {
+                    "expected the identifier of the savepoint as the only argument"
+                },
+            ));
+        }
+
+        if transaction == Transaction::Rollback {
+            method.inputs = vec![Argument {
+                name: syn::Ident::new("savepoint", method.name.span()),
+                ty: syn::parse_quote! { u8 },
+                passing_mode: PassingMode::ByValue,
+                strategy: None,
+            }];
+        }
+        method.transaction = Some(transaction);
+        methods.push(method);
+    }
+
+    Ok(methods)
+}
+
+fn parse_method_groups(
+    input: syn::parse::ParseStream<'_>,
+    nested: bool,
+) -> syn::Result<Vec<Method>> {
+    use syn::{braced, parenthesized, punctuated::Punctuated, Token};
+
+    let mut methods = vec![];
+    let mut inner;
+
+    while !input.is_empty() {
+        let lookahead = input.lookahead1();
+        let mut iterator = None;
+        let (process, opens_handle) = if lookahead.peek(kw::equal) {
+            let _: kw::equal = input.parse()?;
+            (None, false)
+        } else if lookahead.peek(kw::equal_with) {
+            let _: kw::equal_with = input.parse()?;
+            let path;
+            parenthesized!(path in input);
+            (Some(path.parse()?), false)
+        } else if !nested && lookahead.peek(kw::handle) {
+            let _: kw::handle = input.parse()?;
+            (None, true)
+        } else if !nested && lookahead.peek(kw::transactional) {
+            let _: kw::transactional = input.parse()?;
+            braced!(inner in input);
+            methods.extend(parse_transactional_methods(&inner)?);
+            continue;
+        } else if !nested && (lookahead.peek(kw::iterate) || lookahead.peek(kw::iterate_unordered)) {
+            let ordered = input.peek(kw::iterate);
+            let _: syn::Ident = input.parse()?;
+            let steps;
+            parenthesized!(steps in input);
+            let steps: Vec<syn::Ident> =
+                Punctuated::<syn::Ident, Token![,]>::parse_terminated(&steps)?
+                    .into_iter()
+                    .collect();
+            iterator = Some((ordered, steps));
+            (None, false)
+        } else {
+            return Err(lookahead.error());
+        };
+
+        braced!(inner in input);
+        while !inner.is_empty() {
+            let mut method: Method = inner.parse()?;
+            if let Some((ordered, ref steps)) = iterator {
+                if method.handle.is_some() {
+                    return Err(syn::Error::new(
+                        method.name.span(),
+                        "the operations on an iterator are generated",
+                    ));
+                }
+                method.handle = Some(Handle {
+                    kind: HandleKind::Iterator { ordered },
+                    methods: iterator_steps(steps, ordered)?,
+                });
+            }
+            match (opens_handle || iterator.is_some(), method.handle.is_some()) {
+                (true, false) => {
+                    return Err(syn::Error::new(
+                        method.name.span(),
+                        "expected a block of operations on the handle",
+                    ));
+                }
+                (false, true) if nested => {
+                    return Err(syn::Error::new(
+                        method.name.span(),
+                        "nested handles are not supported",
+                    ));
+                }
+                (false, true) => {
+                    return Err(syn::Error::new(
+                        method.name.span(),
+                        "methods opening a handle must be declared in a `handle` group",
+                    ));
+                }
+                _ => {}
+            }
+            if !nested && method.receiver == Receiver::Value {
+                return Err(syn::Error::new(
+                    method.name.span(),
+                    "unexpected by-value receiver",
+                ));
+            }
+            if (method.complexity.is_some() || method.no_alloc) && (nested || method.handle.is_some()) {
+                return Err(syn::Error::new(
+                    method.name.span(),
+                    "complexity bounds and `#[no_alloc]` are not supported on handles",
+                ));
+            }
+            method.process_result.clone_from(&process);
+            methods.push(method);
+        }
+    }
+
+    Ok(methods)
+}
+
 struct Specification {
     model: syn::Path,
     tested: syn::Path,
+    // Whether to generate the operations on a tested instance shared
+    // between threads.
+    concurrent: bool,
+    // Whether to generate the steps forking the model and the tested
+    // instance into further live pairs.
+    fork: bool,
     lifetimes: Vec<syn::LifetimeParam>,
     type_params: Vec<syn::TypeParam>,
     methods: Vec<Method>,
     post: Vec<syn::Stmt>,
     pre: Vec<syn::Stmt>,
+    // Checked on the tested instance alone once an element panicked.
+    consistency: Vec<syn::Stmt>,
+    // The size the cost of the methods with a complexity bound is measured
+    // against, `model.len()` by default.
+    size: Option<syn::Expr>,
+}
+
+/// Checks that the methods of a concurrent structure are plain calls taking
+/// `&self`.
+fn check_concurrent(methods: &[Method]) -> syn::Result<()> {
+    for method in methods {
+        if method.handle.is_some() {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "handles are not supported on a concurrent structure",
+            ));
+        }
+        if method.receiver != Receiver::Ref {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "methods of a concurrent structure must take `&self`",
+            ));
+        }
+        if method.complexity.is_some() || method.no_alloc {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "complexity bounds and `#[no_alloc]` are not supported on a concurrent structure",
+            ));
+        }
+        if method.transaction.is_some() {
+            return Err(syn::Error::new(
+                method.name.span(),
+                "transactional methods are not supported on a concurrent structure",
+            ));
+        }
+    }
+    Ok(())
 }
 
 impl syn::parse::Parse for Specification {
     fn parse(input: syn::parse::ParseStream<'_>) -> syn::Result<Self> {
-        use syn::{braced, parenthesized, Token};
+        use syn::{braced, Token};
 
         let mut model: Option<syn::Path> = None;
         let mut tested: Option<syn::Path> = None;
+        let mut concurrent = false;
+        let mut fork = false;
         let mut lifetimes: Vec<syn::LifetimeParam> = vec![];
         let mut type_params: Vec<syn::TypeParam> = vec![];
         let mut methods: Vec<Method> = vec![];
         let mut post: Vec<syn::Stmt> = vec![];
         let mut pre: Vec<syn::Stmt> = vec![];
+        let mut consistency: Vec<syn::Stmt> = vec![];
+        let mut size: Option<syn::Expr> = None;
 
         while !input.is_empty() {
             let lookahead = input.lookahead1();
@@ -154,6 +659,20 @@ impl syn::parse::Parse for Specification {
                 let _: kw::tested = input.parse()?;
                 let _: Token![=] = input.parse()?;
                 tested = Some(input.parse()?);
+            } else if lookahead.peek(kw::concurrent) {
+                let _: kw::concurrent = input.parse()?;
+                let _: Token![=] = input.parse()?;
+                let value: syn::LitBool = input.parse()?;
+                concurrent = value.value;
+            } else if lookahead.peek(kw::fork) {
+                let _: kw::fork = input.parse()?;
+                let _: Token![=] = input.parse()?;
+                let value: syn::LitBool = input.parse()?;
+                fork = value.value;
+            } else if lookahead.peek(kw::size) {
+                let _: kw::size = input.parse()?;
+                let _: Token![=] = input.parse()?;
+                size = Some(input.parse()?);
             } else if lookahead.peek(kw::type_parameters) {
                 let _: kw::type_parameters = input.parse()?;
                 let _: Token![=] = input.parse()?;
@@ -162,45 +681,18 @@ impl syn::parse::Parse for Specification {
                 type_params = generics.type_params().cloned().collect();
             } else if lookahead.peek(kw::methods) {
                 let outer;
-                let mut inner;
                 let _: kw::methods = input.parse()?;
                 braced!(outer in input);
-
-                while !outer.is_empty() {
-                    let lookahead = outer.lookahead1();
-                    let process = if lookahead.peek(kw::equal) {
-                        let _: kw::equal = outer.parse()?;
-                        None
-                    } else if lookahead.peek(kw::equal_with) {
-                        let _: kw::equal_with = outer.parse()?;
-                        let path;
-                        parenthesized!(path in outer);
-                        Some(path.parse()?)
-                    } else {
-                        return Err(lookahead.error());
-                    };
-
-                    braced!(inner in outer);
-                    while !inner.is_empty() {
-                        let mut method: Method = inner.parse()?;
-                        method.process_result = process.clone();
-                        methods.push(method);
-                    }
-                }
+                methods.extend(parse_method_groups(&outer, false)?);
             } else if lookahead.peek(kw::post) {
-                let inner;
                 let _: kw::post = input.parse()?;
-                braced!(inner in input);
-                while !inner.is_empty() {
-                    post.push(inner.parse()?);
-                }
+                post.extend(parse_block(input)?);
             } else if lookahead.peek(kw::pre) {
-                let inner;
                 let _: kw::pre = input.parse()?;
-                braced!(inner in input);
-                while !inner.is_empty() {
-                    pre.push(inner.parse()?);
-                }
+                pre.extend(parse_block(input)?);
+            } else if lookahead.peek(kw::consistency) {
+                let _: kw::consistency = input.parse()?;
+                consistency.extend(parse_block(input)?);
             } else {
                 return Err(lookahead.error());
             }
@@ -213,41 +705,602 @@ impl syn::parse::Parse for Specification {
         let model = model.ok_or_else(|| input.error("missing `model`"))?;
         let tested = tested.ok_or_else(|| input.error("missing `tested`"))?;
 
+        if concurrent {
+            check_concurrent(&methods)?;
+        }
+
         Ok(Self {
             model,
             tested,
+            concurrent,
+            fork,
             lifetimes,
             type_params,
             methods,
             post,
             pre,
+            consistency,
+            size,
         })
     }
 }
 
-impl quote::ToTokens for Method {
+/// Parses the statements of a braced block.
+fn parse_block(input: syn::parse::ParseStream<'_>) -> syn::Result<Vec<syn::Stmt>> {
+    let inner;
+    syn::braced!(inner in input);
+    let mut stmts = vec![];
+    while !inner.is_empty() {
+        stmts.push(inner.parse()?);
+    }
+    Ok(stmts)
+}
+
+/// Returns those of the type parameters that the arguments of the given
+/// methods refer to.
+fn used_type_params<'s>(
+    type_params: &'s [syn::TypeParam],
+    methods: &[Method],
+) -> Vec<&'s syn::TypeParam> {
+    fn collect_idents(tokens: pm2::TokenStream, idents: &mut Vec<pm2::Ident>) {
+        for token in tokens {
+            match token {
+                pm2::TokenTree::Ident(ident) => idents.push(ident),
+                pm2::TokenTree::Group(group) => collect_idents(group.stream(), idents),
+                _ => {}
+            }
+        }
+    }
+
+    let mut idents = vec![];
+    for input in methods.iter().flat_map(|method| &method.inputs) {
+        let ty = &input.ty;
+        collect_idents(quote! { #ty }, &mut idents);
+    }
+
+    type_params
+        .iter()
+        .filter(|tp| idents.contains(&tp.ident))
+        .collect()
+}
+
+struct Variant<'s> {
+    method: &'s Method,
+    type_params: &'s [syn::TypeParam],
+}
+
+/// The fields of the variant of a method: its arguments, with slices boxed,
+/// and the operations on its handle, if any.
+fn fields(method: &Method, type_params: &[syn::TypeParam]) -> Vec<(syn::Ident, pm2::TokenStream)> {
+    let mut fields: Vec<_> = method
+        .inputs
+        .iter()
+        .map(|input| {
+            let ty = &input.ty;
+            let ty = if let syn::Type::Slice(_) = ty {
+                quote! { Box<#ty> }
+            } else {
+                quote! { #ty }
+            };
+            (input.name.clone(), ty)
+        })
+        .collect();
+    if let Some(ref handle) = method.handle {
+        let module = &method.name;
+        let type_params: Vec<_> = used_type_params(type_params, &handle.methods)
+            .into_iter()
+            .map(|tp| &tp.ident)
+            .collect();
+        fields.push((
+            syn::Ident::new("ops", pm2::Span::call_site()),
+            quote! { Vec<#module::Op<#(#type_params),*>> },
+        ));
+    }
+    fields
+}
+
+impl quote::ToTokens for Variant<'_> {
     fn to_tokens(&self, tokens: &mut pm2::TokenStream) {
-        use pm2::{Delimiter, Group, Ident, Punct, Spacing, Span};
-        use quote::TokenStreamExt;
-
-        tokens.append(self.name.clone());
-
-        if !self.inputs.is_empty() {
-            let mut fields = pm2::TokenStream::new();
-            for input in &self.inputs {
-                fields.append(input.name.clone());
-                fields.append(Punct::new(':', Spacing::Joint));
-                if let syn::Type::Slice(_) = input.ty {
-                    fields.append(Ident::new("Box", Span::call_site()));
-                    fields.append(Punct::new('<', Spacing::Joint));
-                    input.ty.to_tokens(&mut fields);
-                    fields.append(Punct::new('>', Spacing::Joint));
+        let name = &self.method.name;
+        let (names, types): (Vec<_>, Vec<_>) = fields(self.method, self.type_params).into_iter().unzip();
+        if names.is_empty() {
+            tokens.extend(quote! { #name });
+        } else {
+            tokens.extend(quote! { #name { #(#names: #types,)* } });
+        }
+    }
+}
+
+/// The implementation of `rutenspitz::minimize::Shrink` for an `Op` enum,
+/// replacing one field at a time with the simpler values it shrinks to. It
+/// only applies if all the types of the fields implement `Shrink`, which the
+/// higher-ranked bounds let go unchecked otherwise. The enums of the handles
+/// are nested and only have the type parameters their methods use.
+fn shrink_impl(methods: &[Method], type_params: &[syn::TypeParam], nested: bool) -> pm2::TokenStream {
+    let impl_params = if nested {
+        used_type_params(type_params, methods)
+    } else {
+        type_params.iter().collect()
+    };
+    let idents: Vec<_> = impl_params.iter().map(|tp| &tp.ident).collect();
+    let mut bounds = vec![];
+    let arms: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let name = &method.name;
+            let fields = fields(method, type_params);
+            let names: Vec<_> = fields.iter().map(|(name, _)| name).collect();
+            let shrinks = names.iter().map(|field| {
+                let others = names.iter().filter(|other| *other != field);
+                quote! {
+                    simpler_ops.extend(
+                        rutenspitz::minimize::Shrink::shrink(#field)
+                            .into_iter()
+                            .map(|#field| Op::#name { #field, #(#others: #others.clone()),* }),
+                    );
+                }
+            });
+            let arm = if names.is_empty() {
+                quote! { Op::#name => {} }
+            } else {
+                quote! { Op::#name { #(#names),* } => { #(#shrinks)* } }
+            };
+            bounds.extend(fields.into_iter().map(|(_, ty)| ty));
+            arm
+        })
+        .collect();
+
+    quote! {
+        impl<#(#impl_params),*> rutenspitz::minimize::Shrink for Op<#(#idents),*>
+        where
+            #(for<'shrink> #bounds: rutenspitz::minimize::Shrink,)*
+        {
+            fn shrink(&self) -> Vec<Self> {
+                let mut simpler_ops = vec![];
+                match self {
+                    #(#arms),*
+                }
+                simpler_ops
+            }
+        }
+    }
+}
+
+/// The implementation of `rutenspitz::trace::FromTrace` for an `Op` enum,
+/// reading an operation back from the statement its `Display`
+/// implementation writes, with the given receiver. Like that of `Shrink`, it
+/// only applies if all the types of the fields implement `FromTrace`.
+fn parse_impl(
+    methods: &[Method],
+    type_params: &[syn::TypeParam],
+    nested: bool,
+    receiver: &str,
+) -> pm2::TokenStream {
+    let impl_params = if nested {
+        used_type_params(type_params, methods)
+    } else {
+        type_params.iter().collect()
+    };
+    let idents: Vec<_> = impl_params.iter().map(|tp| &tp.ident).collect();
+    let mut bounds = vec![];
+    let arms: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let name = &method.name;
+            let name_str = name.to_string();
+            let arg_count = method.inputs.len();
+            let mut values: Vec<_> = method
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(index, input)| {
+                    let field = &input.name;
+                    let prefix = match input.passing_mode {
+                        PassingMode::ByRef => "&",
+                        PassingMode::ByRefMut => "&mut ",
+                        _ => "",
+                    };
+                    if method.transaction == Some(Transaction::Rollback) {
+                        quote! { #field: call.savepoint(#index)? }
+                    } else {
+                        quote! { #field: call.arg(#index, #prefix)? }
+                    }
+                })
+                .collect();
+            if method.handle.is_some() {
+                values.push(quote! {
+                    ops: handle_ops
+                        .ok_or_else(|| rutenspitz::trace::Error::new(
+                            format!("`{}` opens a handle, which needs a block", #name_str),
+                        ))?
+                        .into_iter()
+                        .map(rutenspitz::trace::FromTrace::from_trace)
+                        .collect::<Result<_, _>>()?
+                });
+            }
+            let op = if values.is_empty() {
+                quote! { Op::#name }
+            } else {
+                quote! { Op::#name { #(#values),* } }
+            };
+            bounds.extend(fields(method, type_params).into_iter().map(|(_, ty)| ty));
+            quote! { #name_str => {
+                call.expect_args(#arg_count)?;
+                Ok(#op)
+            } }
+        })
+        .collect();
+
+    // Only the top-level operations can open handles.
+    let split_block = if methods.iter().any(|method| method.handle.is_some()) {
+        quote! {
+            let (statement, handle_ops) = if statement.trim_start().starts_with('{') {
+                let (opening, ops) = rutenspitz::trace::handle_block(statement)?;
+                (std::borrow::Cow::Owned(opening), Some(ops))
+            } else {
+                (std::borrow::Cow::Borrowed(statement), None)
+            };
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        impl<#(#impl_params),*> rutenspitz::trace::FromTrace for Op<#(#idents),*>
+        where
+            #(for<'trace> #bounds: rutenspitz::trace::FromTrace,)*
+        {
+            fn from_trace(statement: &str) -> Result<Self, rutenspitz::trace::Error> {
+                #split_block
+                let call = rutenspitz::trace::Call::parse(&statement, #receiver)?;
+                match call.name {
+                    #(#arms,)*
+                    name => Err(rutenspitz::trace::Error::new(format!("there is no operation `{}`", name))),
+                }
+            }
+        }
+    }
+}
+
+/// The implementation of `proptest::arbitrary::Arbitrary` for an `Op` enum,
+/// with the `proptest` feature of `rutenspitz`: a union of the strategies of
+/// the variants, whose arguments take the strategy given with
+/// `#[strategy(...)]` or are decoded with `arbitrary` from random bytes, and
+/// whose handle operations are generated the same way. Like that of
+/// `Shrink`, it only applies if all the types of the fields qualify.
+fn proptest_impl(methods: &[Method], type_params: &[syn::TypeParam], nested: bool) -> pm2::TokenStream {
+    if !cfg!(feature = "proptest") {
+        return quote! {};
+    }
+    let impl_params = if nested {
+        used_type_params(type_params, methods)
+    } else {
+        type_params.iter().collect()
+    };
+    let idents: Vec<_> = impl_params.iter().map(|tp| &tp.ident).collect();
+    let mut bounds = vec![];
+    let variants: Vec<_> = methods
+        .iter()
+        .map(|method| {
+            let name = &method.name;
+            let mut names = vec![];
+            let mut strategies = vec![];
+            // The fields of the arguments come first, in the same order.
+            for (input, (field, ty)) in method.inputs.iter().zip(fields(method, type_params)) {
+                names.push(field);
+                strategies.push(if let Some(ref strategy) = input.strategy {
+                    quote! { #strategy }
                 } else {
-                    input.ty.to_tokens(&mut fields);
+                    bounds.push(quote! {
+                        for<'strategy> #ty: rutenspitz::arbitrary::Arbitrary<'strategy>
+                    });
+                    quote! { rutenspitz::property::decoded::<#ty>() }
+                });
+            }
+            if let Some(ref handle) = method.handle {
+                let handle_params: Vec<_> = used_type_params(type_params, &handle.methods)
+                    .into_iter()
+                    .map(|tp| &tp.ident)
+                    .collect();
+                bounds.push(quote! {
+                    for<'strategy> #name::Op<#(#handle_params),*>: rutenspitz::proptest::arbitrary::Arbitrary
+                });
+                names.push(syn::Ident::new("ops", pm2::Span::call_site()));
+                strategies.push(quote! { rutenspitz::property::handle_ops() });
+            }
+            if names.is_empty() {
+                quote! { rutenspitz::proptest::strategy::Just(Op::#name).boxed() }
+            } else {
+                quote! {
+                    (#(#strategies,)*)
+                        .prop_map(|(#(#names,)*)| Op::#name { #(#names),* })
+                        .boxed()
                 }
-                fields.append(Punct::new(',', Spacing::Joint));
             }
-            tokens.append(Group::new(Delimiter::Brace, fields));
+        })
+        .collect();
+
+    quote! {
+        impl<#(#impl_params),*> rutenspitz::proptest::arbitrary::Arbitrary for Op<#(#idents),*>
+        where
+            for<'strategy> Self: std::fmt::Debug + 'static,
+            #(#bounds,)*
+        {
+            type Parameters = ();
+            type Strategy = rutenspitz::proptest::strategy::BoxedStrategy<Self>;
+
+            fn arbitrary_with((): ()) -> Self::Strategy {
+                use rutenspitz::proptest::strategy::Strategy as _;
+                rutenspitz::proptest::strategy::Union::new(vec![
+                    #(#variants),*
+                ])
+                .boxed()
+            }
+        }
+    }
+}
+
+fn call_args(method: &Method) -> Vec<pm2::TokenStream> {
+    method
+        .inputs
+        .iter()
+        .map(|input| {
+            let input_name = &input.name;
+            match input.passing_mode {
+                PassingMode::ByValue => quote! { #input_name.clone() },
+                PassingMode::ByValueInto => quote! { #input_name.clone().into() },
+                PassingMode::ByRef => quote! { #input_name },
+                PassingMode::ByRefMut => quote! { &mut *#input_name },
+                PassingMode::ByClosure(kind, arity) => {
+                    let params: Vec<_> = (0..arity)
+                        .map(|index| quote::format_ident!("arg_{}", index))
+                        .collect();
+                    let call = match (kind, params.as_slice()) {
+                        (ClosureKind::Comparator, [a, b, ..]) => quote! { instance.call(&#a, &#b) },
+                        (ClosureKind::Mutator, [a, ..]) => quote! { instance.call(#a) },
+                        (_, [a, ..]) => quote! { instance.call(&#a) },
+                        (_, []) => quote! { instance.call(&()) },
+                    };
+                    quote! {{
+                        let instance = #input_name.instance();
+                        move |#(#params),*| #call
+                    }}
+                }
+            }
+        })
+        .collect()
+}
+
+fn op_pattern(enum_path: &pm2::TokenStream, method: &Method) -> pm2::TokenStream {
+    let method_name = &method.name;
+    let mut keys: Vec<_> = method.inputs.iter().map(|input| &input.name).collect();
+    let ops = syn::Ident::new("ops", pm2::Span::call_site());
+    if method.handle.is_some() {
+        keys.push(&ops);
+    }
+    if keys.is_empty() {
+        quote! { #enum_path::#method_name }
+    } else {
+        quote! { #enum_path::#method_name { #(ref #keys),* } }
+    }
+}
+
+/// A call of a method on a handle held in an `Option`, so that the methods
+/// taking the handle by value can close it.
+fn handle_call(handle: &syn::Ident, method: &Method) -> pm2::TokenStream {
+    let method_name = &method.name;
+    let args = call_args(method);
+    match method.receiver {
+        Receiver::Value if method_name == "drop" => quote! {
+            std::mem::drop(#handle.take().unwrap())
+        },
+        Receiver::Value => quote! { #handle.take().unwrap().#method_name(#(#args),*) },
+        Receiver::Ref => quote! { #handle.as_ref().unwrap().#method_name(#(#args),*) },
+        Receiver::RefMut => quote! { #handle.as_mut().unwrap().#method_name(#(#args),*) },
+    }
+}
+
+/// Counts the allocations of the tested instance until the end of the
+/// scope, with the `allocations` feature of `rutenspitz`.
+fn track_allocations() -> pm2::TokenStream {
+    if cfg!(feature = "allocations") {
+        quote! { let _tracking = rutenspitz::allocations::Tracking::start(); }
+    } else {
+        quote! {}
+    }
+}
+
+/// How `compare_calls` records the outcome of the model for the regression
+/// tests, in which the call is written as in the trace.
+#[derive(Clone, Copy)]
+enum Record {
+    /// Not at all, the value compared is not what the call returns.
+    Not,
+    /// The value returned, processed by the function of `equal_with`.
+    Value,
+    /// The value compared, which the call yields through this function.
+    Through(&'static str),
+}
+
+/// The comparison of the calls on the model and the tested instance. The
+/// allocations of the tested call are counted if `track_allocations` is set,
+/// for the methods of the tested instance only, not the operations on
+/// handles, which collect the items in places.
+#[allow(clippy::too_many_lines)]
+fn compare_calls(
+    model_call: &pm2::TokenStream,
+    tested_call: &pm2::TokenStream,
+    process_result: Option<&syn::Path>,
+    record: Record,
+    track_allocations: bool,
+) -> pm2::TokenStream {
+    // The outcomes of the model are recorded for the regression tests.
+    let process = match (record, process_result) {
+        (Record::Not, _) => None,
+        (Record::Through(path), _) => Some(quote! { Some(#path) }),
+        (Record::Value, Some(path)) => {
+            let path = quote!(#path).to_string().replace(' ', "");
+            Some(quote! { Some(#path) })
+        }
+        (Record::Value, None) => Some(quote! { None }),
+    };
+    let (record_value, record_panic) = if let Some(process) = process {
+        (
+            quote! {
+                rutenspitz::regression::record_value(#process, || format!("{:?}", model_ret_value));
+            },
+            quote! { rutenspitz::regression::record_panic(); },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+    let track_allocations = if track_allocations {
+        self::track_allocations()
+    } else {
+        quote! {}
+    };
+    let (record_shape, record_panics_first, record_panics_second) = if cfg!(feature = "stats") {
+        (
+            quote! {
+                rutenspitz::stats::record_shape(op_name, {
+                    use rutenspitz::stats::{NoShape as _, ShapeOf as _};
+                    (&rutenspitz::stats::Probe(&model_ret_value)).shape()
+                });
+            },
+            quote! { rutenspitz::stats::record_panics(op_name, true, result.is_err()); },
+            quote! { rutenspitz::stats::record_panics(op_name, false, true); },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {})
+    };
+    // The elements count their calls on each side apart.
+    let model_call = quote! {{
+        let _side = rutenspitz::elements::enter(rutenspitz::elements::Side::Model);
+        #model_call
+    }};
+    let tested_call = quote! {{
+        let _side = rutenspitz::elements::enter(rutenspitz::elements::Side::Tested);
+        #tested_call
+    }};
+    let process_model_ret_value = process_result
+        .map(|p| quote! { #p(model_ret_value) })
+        .unwrap_or(quote! { model_ret_value });
+    let process_tested_ret_value = process_result
+        .map(|p| quote! { #p(tested_ret_value) })
+        .unwrap_or(quote! { tested_ret_value });
+
+    quote! {
+        enum Outcome {
+            Equal,
+            Unequal {
+                model_ret_value_debug: String,
+                tested_ret_value_debug: String,
+            },
+        }
+
+        enum WhichFailed {
+            None(Outcome),
+            First,
+            Second,
+        }
+
+        struct GalaxyBrain<'a> {
+            value: WhichFailed,
+            to_update: &'a mut WhichFailed,
+        }
+
+        impl<'a> Drop for GalaxyBrain<'a> {
+            fn drop(&mut self) {
+                std::mem::swap(self.to_update, &mut self.value);
+            }
+        }
+
+        let mut which_failed = WhichFailed::First;
+
+        {
+            let mut guard = GalaxyBrain {
+                value: WhichFailed::First,
+                to_update: &mut which_failed,
+            };
+
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let model_ret_value = #model_call;
+                guard.value = WhichFailed::Second;
+                let tested_ret_value = {
+                    #track_allocations
+                    #tested_call
+                };
+
+                let model_ret_value = #process_model_ret_value;
+                let tested_ret_value = #process_tested_ret_value;
+                #record_value
+                #record_shape
+                rutenspitz::replay::record_values(
+                    || format!("{:?}", model_ret_value),
+                    || format!("{:?}", tested_ret_value),
+                );
+
+                let outcome = if model_ret_value == tested_ret_value {
+                    Outcome::Equal
+                } else {
+                    Outcome::Unequal {
+                        model_ret_value_debug: format!("{:?}", model_ret_value),
+                        tested_ret_value_debug: format!("{:?}", tested_ret_value),
+                    }
+                };
+                guard.value = WhichFailed::None(outcome);
+            }));
+        }
+
+        // Once an element panicked, the model and the tested instance need
+        // not agree any longer.
+        if rutenspitz::elements::take_injected() {
+            if let WhichFailed::First = which_failed {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let _ = #tested_call;
+                }));
+            }
+            rutenspitz::elements::diverge();
+        }
+
+        match which_failed {
+            WhichFailed::None(outcome) => {
+                if let Outcome::Unequal { model_ret_value_debug, tested_ret_value_debug } = outcome {
+                    rutenspitz::failure::fail(rutenspitz::Failure::ReturnMismatch {
+                        op_index,
+                        op_name,
+                        model: model_ret_value_debug,
+                        tested: tested_ret_value_debug,
+                    });
+                }
+            }
+            WhichFailed::First => {
+                #record_panic
+                // First paniced, see if the second one also does
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    let _ = #tested_call;
+                }));
+                rutenspitz::replay::record_panics(true, result.is_err());
+                #record_panics_first
+                if result.is_ok() {
+                    rutenspitz::failure::fail(rutenspitz::Failure::PanicMismatch {
+                        op_index,
+                        op_name,
+                        model_panicked: true,
+                        tested_panicked: false,
+                    });
+                }
+            }
+            WhichFailed::Second => {
+                rutenspitz::replay::record_panics(false, true);
+                #record_panics_second
+                rutenspitz::failure::fail(rutenspitz::Failure::PanicMismatch {
+                    op_index,
+                    op_name,
+                    model_panicked: false,
+                    tested_panicked: true,
+                });
+            }
         }
     }
 }
@@ -257,146 +1310,638 @@ struct MethodTest<'s> {
     compare: bool,
 }
 
-impl<'s> quote::ToTokens for MethodTest<'s> {
-    #[allow(clippy::too_many_lines)]
-    fn to_tokens(&self, tokens: &mut pm2::TokenStream) {
-        let args: Vec<_> = self
-            .method
-            .inputs
-            .iter()
-            .map(|input| {
-                let input_name = &input.name;
-                match input.passing_mode {
-                    PassingMode::ByValue => quote! { #input_name.clone() },
-                    PassingMode::ByRef => quote! { #input_name },
-                    PassingMode::ByRefMut => quote! { &mut *#input_name },
-                }
-            })
-            .collect();
-
+impl MethodTest<'_> {
+    /// The arm of a method in a `transactional` group. Its counterpart on
+    /// the model is implemented by `rutenspitz::transaction::Savepoints`,
+    /// which panics on a rollback to a closed savepoint, as the tested
+    /// instance should. The identifiers of the savepoints are not compared.
+    /// Without a model there are no savepoints to roll back to.
+    fn transaction_arm(&self, transaction: Transaction) -> pm2::TokenStream {
         let method_name = &self.method.name;
+        let pattern = op_pattern(&quote! { Op }, self.method);
+        let savepoints = quote! { rutenspitz::transaction::Savepoints };
 
-        let keys: Vec<_> = self.method.inputs.iter().map(|input| &input.name).collect();
-        let pattern = if keys.is_empty() {
-            quote! { Op::#method_name }
-        } else {
-            quote! { Op::#method_name { #(ref #keys),* } }
+        let body = match (transaction, self.compare) {
+            // The model opens its savepoint along with the tested instance,
+            // with the identifier it returned.
+            (Transaction::Savepoint, true) => compare_calls(
+                &quote! {},
+                &quote! {{
+                    let id = tested.#method_name();
+                    #savepoints::save(model, id);
+                }},
+                None,
+                Record::Value,
+                true,
+            ),
+            (Transaction::Rollback, true) => {
+                let compare = compare_calls(
+                    &quote! { #savepoints::rollback(model, pick) },
+                    &quote! { tested.#method_name(id.clone()) },
+                    None,
+                    Record::Value,
+                    true,
+                );
+                quote! {
+                    if let Some((pick, id)) = #savepoints::pick(model, *savepoint) {
+                        #compare
+                    }
+                }
+            }
+            (Transaction::Commit, true) => compare_calls(
+                &quote! { #savepoints::commit(model) },
+                &quote! { tested.#method_name() },
+                None,
+                Record::Value,
+                true,
+            ),
+            (Transaction::Savepoint | Transaction::Commit, false) => quote! {
+                let _ = tested.#method_name();
+            },
+            (Transaction::Rollback, false) => quote! {},
         };
 
-        let process_tested_ret_value = self
-            .method
-            .process_result
-            .as_ref()
-            .map(|p| quote! { #p(tested_ret_value) })
-            .unwrap_or(quote! { tested_ret_value });
-
-        if self.compare {
-            let process_model_ret_value = self
-                .method
-                .process_result
-                .as_ref()
-                .map(|p| quote! { #p(model_ret_value) })
-                .unwrap_or(quote! { model_ret_value });
-            tokens.extend(quote! {
-                #pattern => {
-                    enum Outcome {
-                        Equal,
-                        #[cfg(not(fuzzing_debug))]
-                        Unequal,
-                        #[cfg(fuzzing_debug)]
-                        Unequal {
-                            model_ret_value_debug: String,
-                            tested_ret_value_debug: String,
-                        },
-                    }
+        quote! {
+            #pattern => {
+                #body
+            }
+        }
+    }
 
-                    enum WhichFailed {
-                        None(Outcome),
-                        First,
-                        Second,
-                    }
+    /// The comparison of a single operation on the handles of an iterator
+    /// whose order is unspecified. Only whether an item was yielded is
+    /// compared, the items themselves are collected to be compared once the
+    /// iterators are exhausted. They are pushed as options, since extending
+    /// a vector whose type is still unknown with references to `Copy` items
+    /// is ambiguous.
+    fn unordered_step(method: &Method) -> pm2::TokenStream {
+        let model_handle = syn::Ident::new("model_handle", pm2::Span::call_site());
+        let tested_handle = syn::Ident::new("tested_handle", pm2::Span::call_site());
+        let model_call = handle_call(&model_handle, method);
+        let tested_call = handle_call(&tested_handle, method);
+        compare_calls(
+            &quote! {{
+                let item = #model_call;
+                let is_some = item.is_some();
+                model_items.push(item);
+                is_some
+            }},
+            &quote! {{
+                let item = #tested_call;
+                let is_some = item.is_some();
+                tested_items.push(item);
+                is_some
+            }},
+            None,
+            Record::Through("rutenspitz::regression::yielded"),
+            false,
+        )
+    }
 
-                    struct GalaxyBrain<'a> {
-                        value: WhichFailed,
-                        to_update: &'a mut WhichFailed,
+    /// The comparison of what remains in the iterators once the generated
+    /// operations have not closed them.
+    fn exhaust(kind: HandleKind) -> pm2::TokenStream {
+        match kind {
+            HandleKind::Custom => quote! {},
+            HandleKind::Iterator { ordered: true } => {
+                let comparison = compare_calls(
+                    &quote! { model_handle.take().unwrap().collect::<Vec<_>>() },
+                    &quote! { tested_handle.take().unwrap().collect::<Vec<_>>() },
+                    None,
+                    Record::Value,
+                    false,
+                );
+                quote! {
+                    if !closed {
+                        #comparison
                     }
-
-                    impl<'a> Drop for GalaxyBrain<'a> {
-                        fn drop(&mut self) {
-                            std::mem::swap(self.to_update, &mut self.value);
+                }
+            }
+            HandleKind::Iterator { ordered: false } => {
+                let comparison = |record| {
+                    compare_calls(
+                        &quote! {{
+                            let mut items: Vec<_> = model_items.drain(..).flatten().collect();
+                            items.extend(model_handle.take().unwrap());
+                            items.sort();
+                            items
+                        }},
+                        &quote! {{
+                            let mut items: Vec<_> = tested_items.drain(..).flatten().collect();
+                            items.extend(tested_handle.take().unwrap());
+                            items.sort();
+                            items
+                        }},
+                        None,
+                        record,
+                        false,
+                    )
+                };
+                let recorded = comparison(Record::Through("rutenspitz::regression::sorted"));
+                let unrecorded = comparison(Record::Not);
+                quote! {
+                    if !closed {
+                        // The items yielded before are not collected in the
+                        // trace.
+                        if model_items.iter().all(Option::is_none) {
+                            #recorded
+                        } else {
+                            #unrecorded
                         }
                     }
+                }
+            }
+        }
+    }
+}
 
-                    let mut f = WhichFailed::First;
-
-                    {
-                        let mut guard = GalaxyBrain {
-                            value: WhichFailed::First,
-                            to_update: &mut f,
-                        };
+impl quote::ToTokens for MethodTest<'_> {
+    #[allow(clippy::too_many_lines)]
+    fn to_tokens(&self, tokens: &mut pm2::TokenStream) {
+        if let Some(transaction) = self.method.transaction {
+            tokens.extend(self.transaction_arm(transaction));
+            return;
+        }
 
-                        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                            let model_ret_value = model.#method_name(#(#args),*);
-                            guard.value = WhichFailed::Second;
-                            let tested_ret_value = tested.#method_name(#(#args),*);
+        let args = call_args(self.method);
+        let method_name = &self.method.name;
+        let pattern = op_pattern(&quote! { Op }, self.method);
 
-                            let model_ret_value = #process_model_ret_value;
-                            let tested_ret_value = #process_tested_ret_value;
+        let model_handle = syn::Ident::new("model_handle", pm2::Span::call_site());
+        let tested_handle = syn::Ident::new("tested_handle", pm2::Span::call_site());
+        let track_allocations = track_allocations();
 
-                            let outcome = if model_ret_value == tested_ret_value {
-                                Outcome::Equal
-                            } else {
-                                #[cfg(fuzzing_debug)]
-                                {
-                                    Outcome::Unequal {
-                                        model_ret_value_debug: format!("{:?}", model_ret_value),
-                                        tested_ret_value_debug: format!("{:?}", tested_ret_value),
-                                    }
-                                }
-                                #[cfg(not(fuzzing_debug))]
-                                Outcome::Unequal
-                            };
-                            guard.value = WhichFailed::None(outcome);
-                        }));
+        match (&self.method.handle, self.compare) {
+            (None, true) => {
+                let tested_call = match self.method.complexity {
+                    Some(ref bound) if cfg!(feature = "complexity") => quote! {
+                        {
+                            let measurement = rutenspitz::complexity::Measurement::start();
+                            let tested_ret_value = tested.#method_name(#(#args),*);
+                            measurement.finish(
+                                op_name,
+                                complexity_size,
+                                rutenspitz::complexity::Bound::#bound,
+                            );
+                            tested_ret_value
+                        }
+                    },
+                    _ => quote! { tested.#method_name(#(#args),*) },
+                };
+                let comparison = compare_calls(
+                    &quote! { model.#method_name(#(#args),*) },
+                    &tested_call,
+                    self.method.process_result.as_ref(),
+                    Record::Value,
+                    true,
+                );
+                let forbid_allocations = if self.method.no_alloc && cfg!(feature = "allocations") {
+                    quote! { rutenspitz::allocations::forbid(op_index, op_name); }
+                } else {
+                    quote! {}
+                };
+                tokens.extend(quote! {
+                    #pattern => {
+                        #comparison
+                        #forbid_allocations
+                    }
+                });
+            }
+            (None, false) => {
+                tokens.extend(quote! {
+                    #pattern => {
+                        let _ = tested.#method_name(#(#args),*);
                     }
+                });
+            }
+            (Some(handle), true) => {
+                let unordered = handle.kind == HandleKind::Iterator { ordered: false };
+                let sub_op_arms = handle.methods.iter().map(|method| {
+                    let pattern = op_pattern(&quote! { #method_name::Op }, method);
+                    let comparison = if unordered && method.yields_item() {
+                        Self::unordered_step(method)
+                    } else {
+                        compare_calls(
+                            &handle_call(&model_handle, method),
+                            &handle_call(&tested_handle, method),
+                            method.process_result.as_ref(),
+                            Record::Value,
+                            false,
+                        )
+                    };
+                    quote! {
+                        #pattern => {
+                            #comparison
+                        }
+                    }
+                });
+                let state = match handle.kind {
+                    HandleKind::Custom => quote! {},
+                    HandleKind::Iterator { ordered: true } => quote! {
+                        let mut closed = false;
+                    },
+                    HandleKind::Iterator { ordered: false } => quote! {
+                        let mut closed = false;
+                        let mut model_items = Vec::new();
+                        let mut tested_items = Vec::new();
+                    },
+                };
+                let close = match handle.kind {
+                    HandleKind::Custom => quote! {},
+                    HandleKind::Iterator { .. } => quote! { closed = true; },
+                };
+                let exhaust = Self::exhaust(handle.kind);
+                tokens.extend(quote! {
+                    #pattern => {
+                        // The closures have to consume the reborrows so that the handles can
+                        // outlive them.
+                        let mut #model_handle = {
+                            let model = &mut *model;
+                            std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                                let model = model;
+                                model.#method_name(#(#args),*)
+                            })).ok()
+                        };
+                        let mut #tested_handle = {
+                            let tested = &mut *tested;
+                            std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                                let tested = tested;
+                                #track_allocations
+                                tested.#method_name(#(#args),*)
+                            })).ok()
+                        };
 
-                    match f {
-                        WhichFailed::None(outcome) => {
-                            #[cfg(fuzzing_debug)]
-                            if let Outcome::Unequal { model_ret_value_debug, tested_ret_value_debug } = outcome {
-                                rutenspitz::panic!(
-                                    "The return values aren't equal: `{}` != `{}`",
-                                    model_ret_value_debug,
-                                    tested_ret_value_debug
-                                );
+                        match (#model_handle.is_some(), #tested_handle.is_some()) {
+                            (true, false) => {
+                                rutenspitz::regression::record_opened();
+                                rutenspitz::failure::fail(rutenspitz::Failure::PanicMismatch {
+                                    op_index,
+                                    op_name,
+                                    model_panicked: false,
+                                    tested_panicked: true,
+                                });
                             }
-                            #[cfg(not(fuzzing_debug))]
-                            if let Outcome::Unequal = outcome {
-                                rutenspitz::panic!("The return values aren't equal");
+                            (false, true) => {
+                                rutenspitz::regression::record_panic();
+                                rutenspitz::failure::fail(rutenspitz::Failure::PanicMismatch {
+                                    op_index,
+                                    op_name,
+                                    model_panicked: true,
+                                    tested_panicked: false,
+                                });
                             }
-                        }
-                        WhichFailed::First => {
-                            // First paniced, see if the second one also does
-                            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                                let _ = tested.#method_name(#(#args),*);
-                            }));
-                            if result.is_ok() {
-                                rutenspitz::panic!("Implementation did not panic while the model did");
+                            (false, false) => rutenspitz::regression::record_panic(),
+                            (true, true) => {
+                                rutenspitz::regression::record_opened();
+                                #state
+                                for op in ops {
+                                    match op {
+                                        #(#sub_op_arms),*
+                                    }
+                                    if op.closes_handle() {
+                                        #close
+                                        break;
+                                    }
+                                }
+                                #exhaust
                             }
                         }
-                        WhichFailed::Second => {
-                            rutenspitz::panic!("Implementation panicked while the model did not");
+                    }
+                });
+            }
+            (Some(handle), false) => {
+                let sub_op_arms = handle.methods.iter().map(|method| {
+                    let pattern = op_pattern(&quote! { #method_name::Op }, method);
+                    let call = handle_call(&tested_handle, method);
+                    quote! {
+                        #pattern => {
+                            let _ = #call;
                         }
                     }
-                }
+                });
+                let exhaust = match handle.kind {
+                    HandleKind::Custom => quote! {},
+                    HandleKind::Iterator { .. } => quote! {
+                        if let Some(handle) = #tested_handle {
+                            handle.for_each(drop);
+                        }
+                    },
+                };
+                tokens.extend(quote! {
+                    #pattern => {
+                        let mut #tested_handle = Some(tested.#method_name(#(#args),*));
+                        for op in ops {
+                            match op {
+                                #(#sub_op_arms),*
+                            }
+                            if op.closes_handle() {
+                                break;
+                            }
+                        }
+                        #exhaust
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// The arm executing the method on a tested instance shared between threads,
+/// which defers the call on the model until the history is checked. The
+/// deferred call owns clones of the arguments, so that it can outlive the
+/// operation.
+fn concurrent_call(method: &Method, model: &syn::Path) -> pm2::TokenStream {
+    let args = call_args(method);
+    let method_name = &method.name;
+    let pattern = op_pattern(&quote! { Op }, method);
+    let names: Vec<_> = method.inputs.iter().map(|input| &input.name).collect();
+    let process = |call: pm2::TokenStream| match method.process_result {
+        Some(ref p) => quote! { #p(#call) },
+        None => call,
+    };
+    let tested_call = process(quote! { tested.#method_name(#(#args),*) });
+    let model_call = process(quote! { model.#method_name(#(#args),*) });
+
+    quote! {
+        #pattern => {
+            let tested_ret_value = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                #tested_call
+            }))
+            .ok();
+            #(let #names = #names.clone();)*
+            rutenspitz::linearizability::Observed::new(tested_ret_value, move |model: &mut #model| {
+                #(let #names = &#names;)*
+                #model_call
+            })
+        }
+    }
+}
+
+/// Runs the statements of the `post` block, turning a panic in them into a
+/// failure of the operation.
+fn check_invariants(post: &[syn::Stmt]) -> pm2::TokenStream {
+    if post.is_empty() {
+        return quote! {};
+    }
+    quote! {
+        let invariants = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            #(#post)*
+        }));
+        if let Err(payload) = invariants {
+            if payload.is::<rutenspitz::Failure>() {
+                std::panic::resume_unwind(payload);
+            }
+            rutenspitz::failure::fail(rutenspitz::Failure::InvariantViolation {
+                op_index,
+                op_name,
+                message: rutenspitz::failure::panic_message(&*payload),
             });
-        } else {
-            tokens.extend(quote! {
-                #pattern => {
-                    let _ = tested.#method_name(#(#args),*);
+        }
+    }
+}
+
+/// The implementation of `rutenspitz::linearizability::Operation`, if the
+/// specification asks for it.
+fn concurrent_impl(spec: &Specification) -> pm2::TokenStream {
+    if !spec.concurrent {
+        return quote! {};
+    }
+
+    let lifetimes = &spec.lifetimes;
+    let type_params_with_bounds = &spec.type_params;
+    let type_params: Vec<_> = type_params_with_bounds.iter().map(|tp| &tp.ident).collect();
+    let model = &spec.model;
+    let tested = &spec.tested;
+    let concurrent_calls = spec
+        .methods
+        .iter()
+        .map(|method| concurrent_call(method, model));
+
+    quote! {
+        impl<#(#lifetimes,)* #(#type_params_with_bounds),*>
+            rutenspitz::linearizability::Operation<#model, #tested> for Op<#(#type_params),*>
+        {
+            fn execute_concurrently<'observed>(
+                &self,
+                tested: &#tested,
+            ) -> rutenspitz::linearizability::Observed<'observed, #model>
+            where
+                Self: 'observed,
+            {
+                match self {
+                    #(#concurrent_calls),*
                 }
-            });
+            }
+        }
+    }
+}
+
+/// The steps forking the live pairs or executing an operation on one of
+/// them, if the specification asks for them.
+fn fork_step(spec: &Specification) -> pm2::TokenStream {
+    if !spec.fork {
+        return quote! {};
+    }
+
+    let type_params: Vec<_> = spec.type_params.iter().map(|tp| &tp.ident).collect();
+
+    quote! {
+        /// A step of a sequence: a fork of one of the live pairs of a model
+        /// and a tested instance, or an operation on one of them. See
+        /// `rutenspitz::fork`.
+        pub type Step<#(#type_params),*> = rutenspitz::fork::Step<Op<#(#type_params),*>>;
+    }
+}
+
+fn format_call(
+    enum_path: &pm2::TokenStream,
+    method: &Method,
+    receiver: &str,
+) -> pm2::TokenStream {
+    let args: Vec<_> = method
+        .inputs
+        .iter()
+        .map(|input| match input.passing_mode {
+            PassingMode::ByValue | PassingMode::ByValueInto => "{:?}",
+            PassingMode::ByRef => "&{:?}",
+            PassingMode::ByRefMut => "&mut {:?}",
+            PassingMode::ByClosure(..) => "{}",
+        })
+        .collect();
+
+    let method_name = &method.name;
+    let keys: Vec<_> = method.inputs.iter().map(|input| &input.name).collect();
+    let values: Vec<_> = method
+        .inputs
+        .iter()
+        .map(|input| {
+            let name = &input.name;
+            if let PassingMode::ByClosure(_, arity) = input.passing_mode {
+                quote! { #name.display(#arity) }
+            } else {
+                quote! { #name }
+            }
+        })
+        .collect();
+
+    if let Some(transaction) = method.transaction {
+        // The identifiers of the savepoints are kept in `savepoints`, a
+        // `rutenspitz::transaction::Ids`.
+        let (pattern, format_str) = match transaction {
+            Transaction::Savepoint => (
+                quote! { #enum_path::#method_name },
+                format!("savepoints.save({receiver}.{method_name}());"),
+            ),
+            Transaction::Rollback => (
+                quote! { #enum_path::#method_name { savepoint } },
+                format!("savepoints.rollback({{}}, |id| {receiver}.{method_name}(id));"),
+            ),
+            Transaction::Commit => (
+                quote! { #enum_path::#method_name },
+                format!("savepoints.commit(|| {receiver}.{method_name}());"),
+            ),
+        };
+        return quote! { #pattern =>
+            write!(formatter, #format_str, #(#values),*)
+        };
+    }
+
+    if let Some(ref handle) = method.handle {
+        let format_str = format!(
+            "{{{{\n    let mut h = {}.{}({});\n",
+            receiver,
+            method_name,
+            args.join(", ")
+        );
+        let exhaust = match handle.kind {
+            HandleKind::Custom => quote! {},
+            HandleKind::Iterator { .. } => quote! {
+                if !ops.iter().any(|op| op.closes_handle()) {
+                    writeln!(formatter, "    h.collect::<Vec<_>>();")?;
+                }
+            },
+        };
+        let ops = syn::Ident::new("ops", pm2::Span::call_site());
+        let pattern = quote! { #enum_path::#method_name { #(#keys,)* #ops } };
+        return quote! { #pattern => {
+            write!(formatter, #format_str, #(#values),*)?;
+            for op in #ops {
+                writeln!(formatter, "    {}", op)?;
+                if op.closes_handle() {
+                    break;
+                }
+            }
+            #exhaust
+            write!(formatter, "}}")
+        } };
+    }
+
+    let format_str = if method_name == "drop" && method.receiver == Receiver::Value {
+        format!("drop({receiver});")
+    } else {
+        format!("{}.{}({});", receiver, method_name, args.join(", "))
+    };
+    let pattern = if keys.is_empty() {
+        quote! { #enum_path::#method_name }
+    } else {
+        quote! { #enum_path::#method_name { #(#keys),* } }
+    };
+
+    quote! { #pattern =>
+        write!(formatter, #format_str, #(#values),*)
+    }
+}
+
+/// The derives of `Serialize` and `Deserialize` for the `Op` enums, with the
+/// `serde` feature of `rutenspitz`.
+fn serde_derives() -> pm2::TokenStream {
+    if cfg!(feature = "serde") {
+        quote! {
+            #[derive(rutenspitz::serde::Serialize, rutenspitz::serde::Deserialize)]
+            #[serde(crate = "rutenspitz::serde")]
         }
+    } else {
+        quote! {}
+    }
+}
+
+struct HandleModule<'s> {
+    method: &'s Method,
+    type_params: &'s [syn::TypeParam],
+}
+
+impl quote::ToTokens for HandleModule<'_> {
+    fn to_tokens(&self, tokens: &mut pm2::TokenStream) {
+        let Some(Handle { ref methods, .. }) = self.method.handle else {
+            return;
+        };
+
+        let module = &self.method.name;
+        let type_params_with_bounds = used_type_params(self.type_params, methods);
+        let type_params: Vec<_> = type_params_with_bounds
+            .iter()
+            .map(|tp| tp.ident.clone())
+            .collect();
+
+        let variants: Vec<_> = methods
+            .iter()
+            .map(|method| Variant {
+                method,
+                type_params: self.type_params,
+            })
+            .collect();
+        let format_calls: Vec<_> = methods
+            .iter()
+            .map(|method| format_call(&quote! { Op }, method, "h"))
+            .collect();
+        let closing: Vec<_> = methods
+            .iter()
+            .filter(|method| method.closes_handle())
+            .map(|method| &method.name)
+            .collect();
+        let closes_handle = if closing.is_empty() {
+            quote! { false }
+        } else {
+            quote! { matches!(self, #(Op::#closing { .. })|*) }
+        };
+        let shrink_impl = shrink_impl(methods, self.type_params, true);
+        let parse_impl = parse_impl(methods, self.type_params, true, "h");
+        let proptest_impl = proptest_impl(methods, self.type_params, true);
+        let serde_derives = serde_derives();
+
+        tokens.extend(quote! {
+            pub mod #module {
+                use super::*;
+
+                #[allow(non_camel_case_types)]
+                #[derive(rutenspitz::derive::Arbitrary, rutenspitz::derive::IntoStaticStr, Clone, Debug, PartialEq)]
+                #serde_derives
+                pub enum Op<#(#type_params_with_bounds),*> {
+                    #(#variants),*
+                }
+
+                impl<#(#type_params_with_bounds),*> std::fmt::Display for Op<#(#type_params),*> {
+                    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        match self {
+                            #(#format_calls),*
+                        }
+                    }
+                }
+
+                impl<#(#type_params_with_bounds),*> Op<#(#type_params),*> {
+                    /// Whether the operation consumes the handle.
+                    pub fn closes_handle(&self) -> bool {
+                        #closes_handle
+                    }
+                }
+
+                #shrink_impl
+
+                #parse_impl
+
+                #proptest_impl
+            }
+        });
     }
 }
 
@@ -404,8 +1949,8 @@ struct OperationEnum<'s> {
     spec: &'s Specification,
 }
 
-impl<'s> quote::ToTokens for OperationEnum<'s> {
-    #[allow(clippy::cognitive_complexity)]
+impl quote::ToTokens for OperationEnum<'_> {
+    #[allow(clippy::cognitive_complexity, clippy::too_many_lines)]
     fn to_tokens(&self, tokens: &mut pm2::TokenStream) {
         let lifetimes = &self.spec.lifetimes;
         let type_params_with_bounds = &self.spec.type_params;
@@ -416,7 +1961,24 @@ impl<'s> quote::ToTokens for OperationEnum<'s> {
 
         let model = &self.spec.model;
         let tested = &self.spec.tested;
-        let variants = &self.spec.methods;
+        let variants: Vec<_> = self
+            .spec
+            .methods
+            .iter()
+            .map(|method| Variant {
+                method,
+                type_params: type_params_with_bounds,
+            })
+            .collect();
+        let handle_modules: Vec<_> = self
+            .spec
+            .methods
+            .iter()
+            .map(|method| HandleModule {
+                method,
+                type_params: type_params_with_bounds,
+            })
+            .collect();
 
         let comp_method_tests: Vec<_> = self
             .spec
@@ -442,50 +2004,65 @@ impl<'s> quote::ToTokens for OperationEnum<'s> {
             .spec
             .methods
             .iter()
-            .map(|method| {
-                let args: Vec<_> = method
-                    .inputs
-                    .iter()
-                    .map(|input| match input.passing_mode {
-                        PassingMode::ByValue => "{:?}",
-                        PassingMode::ByRef => "&{:?}",
-                        PassingMode::ByRefMut => "&mut {:?}",
-                    })
-                    .collect();
-
-                let method_name = &method.name;
-                let format_str = format!("v.{}({});", method_name, args.join(", "));
-                let keys: Vec<_> = method.inputs.iter().map(|input| &input.name).collect();
-                let pattern = if keys.is_empty() {
-                    quote! { Op::#method_name }
-                } else {
-                    quote! { Op::#method_name { #(#keys),* } }
-                };
-
-                quote! { #pattern =>
-                    write!(f, #format_str, #(#keys),*)
-                }
-            })
+            .map(|method| format_call(&quote! { Op }, method, "v"))
             .collect();
 
-        let post = &self.spec.post;
         let pre = &self.spec.pre;
+        let post = check_invariants(&self.spec.post);
+        let consistency = check_invariants(&self.spec.consistency);
+
+        let shrink_impl = shrink_impl(&self.spec.methods, type_params_with_bounds, false);
+        let parse_impl = parse_impl(&self.spec.methods, type_params_with_bounds, false, "v");
+        let proptest_impl = proptest_impl(&self.spec.methods, type_params_with_bounds, false);
+        let serde_derives = serde_derives();
+        let concurrent_impl = concurrent_impl(self.spec);
+        let fork_step = fork_step(self.spec);
+        let complexity_size = if cfg!(feature = "complexity")
+            && self.spec.methods.iter().any(|method| method.complexity.is_some())
+        {
+            let size = self
+                .spec
+                .size
+                .as_ref()
+                .map_or_else(|| quote! { model.len() }, |size| quote! { #size });
+            quote! { let complexity_size: usize = #size; }
+        } else {
+            quote! {}
+        };
+        let record_op = if cfg!(feature = "stats") {
+            quote! { rutenspitz::stats::record_op(op_name); }
+        } else {
+            quote! {}
+        };
+        let (start_allocations, record_allocations) = match (cfg!(feature = "allocations"), cfg!(feature = "stats")) {
+            (true, true) => (
+                quote! { rutenspitz::allocations::start_op(); },
+                quote! { rutenspitz::stats::record_allocations(op_name, rutenspitz::allocations::op()); },
+            ),
+            (true, false) => (quote! { rutenspitz::allocations::start_op(); }, quote! {}),
+            (false, _) => (quote! {}, quote! {}),
+        };
 
         tokens.extend(quote! {
+            #(#handle_modules)*
+
             #[allow(non_camel_case_types)]
             #[derive(rutenspitz::derive::Arbitrary, rutenspitz::derive::IntoStaticStr, Clone, Debug, PartialEq)]
+            #serde_derives
             pub enum Op<#(#type_params_with_bounds),*> {
                 #(#variants),*
             }
 
             impl<#(#type_params_with_bounds),*> std::fmt::Display for Op<#(#type_params),*> {
-                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                     match self {
                         #(#format_calls),*
                     }
                 }
             }
 
+            // Some of the handles, such as iterators, need not implement `Drop`.
+            #[allow(clippy::drop_non_drop)]
             impl<#(#type_params_with_bounds),*> Op<#(#type_params),*> {
                 pub fn execute <#(#lifetimes),*> (self, tested: &mut #tested) {
                     match &self {
@@ -497,12 +2074,35 @@ impl<'s> quote::ToTokens for OperationEnum<'s> {
                     #[cfg(not(fuzzing_debug))]
                     rutenspitz::lazy_static::initialize(&rutenspitz::NON_DEBUG_PANIC_HOOK);
 
+                    let op_index = rutenspitz::failure::next_op_index();
                     let op_name: &'static str = From::from(&self);
+                    rutenspitz::recent::push(op_index, &self);
+                    #record_op
+                    // Once an element panicked, the operations only run on
+                    // the tested instance.
+                    if rutenspitz::elements::diverged() {
+                        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                            let _side = rutenspitz::elements::enter(rutenspitz::elements::Side::Tested);
+                            self.execute(&mut *tested)
+                        }));
+                        #consistency
+                        return;
+                    }
+                    #start_allocations
+                    #complexity_size
                     #(#pre)*
-                    match &self {
+                    let compared = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match &self {
                         #(#comp_method_tests),*
+                    }));
+                    if let Err(payload) = compared {
+                        if !payload.is::<rutenspitz::elements::Diverged>() {
+                            std::panic::resume_unwind(payload);
+                        }
+                        #consistency
+                        return;
                     }
-                    #(#post)*
+                    #record_allocations
+                    #post
                 }
 
                 #[inline(always)]
@@ -511,6 +2111,62 @@ impl<'s> quote::ToTokens for OperationEnum<'s> {
                     trace.push_str(&format!("{}\n", self.to_string()));
                 }
             }
+
+            impl<#(#lifetimes,)* #(#type_params_with_bounds),*> rutenspitz::Operation<#model, #tested>
+                for Op<#(#type_params),*>
+            {
+                fn execute_and_compare(self, model: &mut #model, tested: &mut #tested) {
+                    Op::execute_and_compare(self, model, tested);
+                }
+            }
+
+            #shrink_impl
+
+            #parse_impl
+
+            #proptest_impl
+
+            impl<#(#type_params_with_bounds),*> Op<#(#type_params),*>
+            where
+                for<'trace> Self: rutenspitz::trace::FromTrace,
+            {
+                /// Reads an operation back from the statement written for it
+                /// in a trace.
+                pub fn from_trace_line(line: &str) -> Result<Self, rutenspitz::trace::Error> {
+                    rutenspitz::trace::FromTrace::from_trace(line)
+                }
+            }
+
+            /// Reads the operations back from a trace, as written by
+            /// `append_to_trace`.
+            pub fn parse_trace<#(#type_params_with_bounds),*>(
+                trace: &str,
+            ) -> Result<Vec<Op<#(#type_params),*>>, rutenspitz::trace::Error>
+            where
+                for<'trace> Op<#(#type_params),*>: rutenspitz::trace::FromTrace,
+            {
+                rutenspitz::trace::parse(trace)
+            }
+
+            /// Replays an input, such as a crash file, the way the fuzzing
+            /// loop runs it, with `new` building the model and the tested
+            /// instance from its start. See `rutenspitz::replay`.
+            pub fn replay<'data, ReplayModel, ReplayTested, #(#type_params_with_bounds),*>(
+                data: &'data [u8],
+                new: impl FnOnce(
+                    &mut rutenspitz::arbitrary::Unstructured<'data>,
+                ) -> rutenspitz::arbitrary::Result<(ReplayModel, ReplayTested)>,
+            ) -> rutenspitz::arbitrary::Result<rutenspitz::replay::Report>
+            where
+                Op<#(#type_params),*>: rutenspitz::arbitrary::Arbitrary<'data>
+                    + rutenspitz::Operation<ReplayModel, ReplayTested>,
+            {
+                rutenspitz::replay::replay_input::<_, _, Op<#(#type_params),*>, _>(data, new)
+            }
+
+            #concurrent_impl
+
+            #fork_step
         });
     }
 }